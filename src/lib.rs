@@ -0,0 +1,6043 @@
+//! Solver for the Bedlam cube and other polycube packing puzzles
+#![allow(clippy::needless_range_loop)]
+
+/// Largest cube side length that fits the bits of a BitCube
+pub const MAX_CUBE_SIZE: usize = 5;
+/// Most cells a box can have for its bits to fit in a BitCube
+pub const MAX_CELLS: usize = 128;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+pub struct Coords(pub usize, pub usize, pub usize);
+
+/// Set of cells in the cube, one bit per cell
+///
+/// Backed by a u128 so that cubes up to 5x5x5 fit, the layout of the bits is given by
+/// `Puzzle::bit_index`.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BitCube(pub u128);
+
+impl BitCube {
+    /// Block with the lowest `num_bits` bits set
+    pub fn full(num_bits: usize) -> Self {
+        Self(u128::MAX >> (128 - num_bits))
+    }
+    pub fn set_bit(&mut self, index: usize) {
+        self.0 |= 1 << index;
+    }
+    pub fn test_bit(&self, index: usize) -> bool {
+        (self.0 >> index) & 1 == 1
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    pub fn is_disjoint(&self, other: BitCube) -> bool {
+        self.0 & other.0 == 0
+    }
+    pub fn count_ones(&self) -> u32 {
+        self.0.count_ones()
+    }
+    pub fn trailing_ones(&self) -> u32 {
+        self.0.trailing_ones()
+    }
+    pub fn trailing_zeros(&self) -> u32 {
+        self.0.trailing_zeros()
+    }
+    /// Block with only the lowest set bit of this block
+    pub fn lowest_bit(&self) -> Self {
+        Self(self.0 & self.0.wrapping_neg())
+    }
+}
+
+impl std::ops::BitOr for BitCube {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl std::ops::BitOrAssign for BitCube {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+impl std::ops::BitAnd for BitCube {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+impl std::ops::BitAndAssign for BitCube {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+impl std::ops::Not for BitCube {
+    type Output = Self;
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+impl std::ops::Shl<usize> for BitCube {
+    type Output = Self;
+    fn shl(self, rhs: usize) -> Self {
+        Self(self.0 << rhs)
+    }
+}
+impl std::ops::Shr<usize> for BitCube {
+    type Output = Self;
+    fn shr(self, rhs: usize) -> Self {
+        Self(self.0 >> rhs)
+    }
+}
+impl std::fmt::LowerHex for BitCube {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// Dimensions of the puzzle, the bit layout of blocks is derived from these
+#[derive(Clone, Copy)]
+pub struct Puzzle {
+    /// Side lengths of the box along x, y and z, all the same for a cube
+    pub dims: [usize; 3],
+    /// Number of pieces to place
+    pub num_pieces: usize,
+
+    /// Bits with z = 0, z = depth - 1, y = 0 and y = height - 1 respectively
+    z_first: BitCube,
+    z_last: BitCube,
+    y_first: BitCube,
+    y_last: BitCube,
+
+    /// Bit that each bit moves to when the box is rotated in place by 90 degrees around
+    /// X, Y and Z, `LOST_BIT` if the cell ends up outside of a box that isn't a cube
+    rotations: [[u8; MAX_CELLS]; 3],
+    /// Same for the orientations `nr` of `orient_piece`
+    orientations: [[u8; MAX_CELLS]; 24],
+}
+
+/// Entry of the rotation tables of `Puzzle` for cells that are rotated out of the box
+const LOST_BIT: u8 = u8::MAX;
+
+impl Puzzle {
+    /// Puzzle for filling a cube with side length `size`
+    pub fn new(size: usize, num_pieces: usize) -> Self {
+        assert!(
+            (1..=MAX_CUBE_SIZE).contains(&size),
+            "Cube size must be between 1 and {}",
+            MAX_CUBE_SIZE
+        );
+        Self::new_box([size; 3], num_pieces)
+    }
+    /// Puzzle for filling a box with the given side lengths along x, y and z
+    pub fn new_box(dims: [usize; 3], num_pieces: usize) -> Self {
+        assert!(
+            dims.iter().all(|d| *d >= 1) && dims.iter().product::<usize>() <= MAX_CELLS,
+            "Box sides must be at least 1, with at most {} cells",
+            MAX_CELLS
+        );
+        let [size_x, size_y, size_z] = dims;
+        let mut puzzle = Self {
+            dims,
+            num_pieces,
+            z_first: BitCube::default(),
+            z_last: BitCube::default(),
+            y_first: BitCube::default(),
+            y_last: BitCube::default(),
+            rotations: [[LOST_BIT; MAX_CELLS]; 3],
+            orientations: [[LOST_BIT; MAX_CELLS]; 24],
+        };
+        // Bit of a rotated cell, if it is still in the box
+        let rotated_bit = |puzzle: &Self, (_, (x, y, z)): RotatedCell| {
+            if x < size_x && y < size_y && z < size_z {
+                puzzle.bit_index(x, y, z) as u8
+            } else {
+                LOST_BIT
+            }
+        };
+        for x in 0..size_x {
+            for y in 0..size_y {
+                for z in 0..size_z {
+                    let bit = pack_bit(&puzzle, true, x, y, z);
+                    if z == 0 {
+                        puzzle.z_first |= bit;
+                    }
+                    if z == size_z - 1 {
+                        puzzle.z_last |= bit;
+                    }
+                    if y == 0 {
+                        puzzle.y_first |= bit;
+                    }
+                    if y == size_y - 1 {
+                        puzzle.y_last |= bit;
+                    }
+
+                    let index = puzzle.bit_index(x, y, z);
+                    for axis in [Axis::X, Axis::Y, Axis::Z] {
+                        let rotated = rotate_cell(dims, (x, y, z), axis);
+                        puzzle.rotations[axis as usize][index] = rotated_bit(&puzzle, rotated);
+                    }
+                    for nr in 0..24 {
+                        let rotated = orient_cell(dims, (x, y, z), nr);
+                        puzzle.orientations[nr][index] = rotated_bit(&puzzle, rotated);
+                    }
+                }
+            }
+        }
+        puzzle
+    }
+    /// Whether all sides of the box are the same
+    pub fn is_cube(&self) -> bool {
+        self.dims[0] == self.dims[1] && self.dims[1] == self.dims[2]
+    }
+    /// Orientations `nr` of `orient_piece` that map the box onto itself
+    ///
+    /// All 24 for a cube, fewer for other boxes.
+    pub fn symmetries(&self) -> Vec<usize> {
+        (0..24)
+            .filter(|nr| orient_cell(self.dims, (0, 0, 0), *nr).0 == self.dims)
+            .collect()
+    }
+    /// Number of cells in the cube
+    pub fn num_bits(&self) -> usize {
+        self.dims.iter().product()
+    }
+    /// Mask with all bits of the cube set
+    pub fn mask(&self) -> BitCube {
+        BitCube::full(self.num_bits())
+    }
+    /// Index of the bit for cell x, y, z, `(x * height + y) * depth + z`
+    ///
+    /// z varies fastest, so in a 4x4x4 cube bit 0 is (0, 0, 0), bit 1 is (0, 0, 1), bit 4
+    /// is (0, 1, 0) and bit 16 is (1, 0, 0). Every bitmask of the library, placements and
+    /// solutions alike, uses this layout.
+    pub fn bit_index(&self, x: usize, y: usize, z: usize) -> usize {
+        (x * self.dims[1] + y) * self.dims[2] + z
+    }
+    /// Move every set bit of the block to the bit given by the table, dropping the
+    /// `LOST_BIT` ones
+    fn permute_bits(table: &[u8; MAX_CELLS], block: BitCube) -> BitCube {
+        let mut bits = block;
+        let mut permuted = BitCube::default();
+        while !bits.is_empty() {
+            let target = table[bits.trailing_zeros() as usize];
+            bits &= !bits.lowest_bit();
+            if target != LOST_BIT {
+                permuted.0 |= 1 << target;
+            }
+        }
+        permuted
+    }
+    /// Returns the block grown by one cell in each face direction
+    pub fn grow(&self, block: BitCube) -> BitCube {
+        let (row, layer) = (self.dims[2], self.dims[1] * self.dims[2]);
+        (block
+            | ((block << 1) & !self.z_first)
+            | ((block >> 1) & !self.z_last)
+            | ((block << row) & !self.y_first)
+            | ((block >> row) & !self.y_last)
+            | (block << layer)
+            | (block >> layer))
+            & self.mask()
+    }
+}
+
+/// Placement of every piece, indexed by piece id, empty for pieces that aren't placed
+///
+/// Solutions are equal if every piece has the same placement, rotations of a solution are
+/// told apart by `canonical_key`.
+#[derive(Default, Clone, PartialEq, Eq, Hash)]
+pub struct Solution(pub Vec<BitCube>);
+
+impl Solution {
+    /// Displays the solution in the text format of `write_solution`
+    pub fn display<'a>(&'a self, puzzle: &'a Puzzle, pieces: &'a [Piece]) -> SolutionDisplay<'a> {
+        SolutionDisplay {
+            puzzle,
+            pieces,
+            solution: self,
+        }
+    }
+}
+
+/// Solution formatted like `write_solution` writes it, see `Solution::display`
+pub struct SolutionDisplay<'a> {
+    puzzle: &'a Puzzle,
+    pieces: &'a [Piece],
+    solution: &'a Solution,
+}
+
+// With more than 26 pieces the labels are padded to the same width and cells are
+// separated by a space, so that the columns line up.
+impl std::fmt::Display for SolutionDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let puzzle = self.puzzle;
+        let arr = solution_labels(puzzle, self.pieces, self.solution);
+        let width = label_width(self.pieces.len());
+
+        for z in 0..puzzle.dims[2] {
+            for y in 0..puzzle.dims[1] {
+                for x in 0..puzzle.dims[0] {
+                    if width > 1 && x != 0 {
+                        write!(f, " ")?;
+                    }
+                    let label = arr[puzzle.bit_index(x, y, z)].as_deref().unwrap_or(".");
+                    if x == puzzle.dims[0] - 1 && y == puzzle.dims[1] - 1 {
+                        // No padding at the end of the line
+                        write!(f, "{}", label)?;
+                    } else {
+                        write!(f, "{:<width$}", label)?;
+                    }
+                }
+                if y != puzzle.dims[1] - 1 {
+                    write!(f, "    ")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A piece of the puzzle, as read from the piece file
+#[derive(Clone)]
+pub struct Piece {
+    /// Index of the piece, also its index in a `Solution`
+    pub id: usize,
+    /// Human readable name, e.g. "red cross"
+    pub name: Option<String>,
+    pub bits: BitCube,
+    /// Only placed in the orientation it is given in, never rotated
+    pub fixed: bool,
+}
+
+impl Piece {
+    /// Label of the piece in written solutions, see `piece_label`
+    pub fn label(&self) -> String {
+        piece_label(self.id)
+    }
+    /// Name of the piece if it has one, otherwise its label
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.label())
+    }
+    /// Every placement of the piece in the box, as bitmasks laid out by `Puzzle::bit_index`,
+    /// see `generate_placements`
+    pub fn placements(&self, puzzle: &Puzzle) -> Vec<BitCube> {
+        generate_placements(puzzle, self.bits, self.fixed, false)
+    }
+}
+
+/// Label of the piece with the given id: A, B, ..., Z, AA, AB, ..., like spreadsheet columns
+pub fn piece_label(id: usize) -> String {
+    let mut label = Vec::new();
+    let mut n = id + 1;
+    while n > 0 {
+        label.push(b'A' + ((n - 1) % 26) as u8);
+        n = (n - 1) / 26;
+    }
+    label.iter().rev().map(|c| *c as char).collect()
+}
+
+/// Width of the longest label of `num_pieces` pieces, that all cells are padded to
+/// in written solutions
+pub fn label_width(num_pieces: usize) -> usize {
+    piece_label(num_pieces.saturating_sub(1)).len()
+}
+
+pub fn pack_bit(puzzle: &Puzzle, b: bool, x: usize, y: usize, z: usize) -> BitCube {
+    let mut block = BitCube::default();
+    if b {
+        block.set_bit(puzzle.bit_index(x, y, z));
+    }
+    block
+}
+pub fn unpack_bit(puzzle: &Puzzle, block: BitCube, x: usize, y: usize, z: usize) -> bool {
+    block.test_bit(puzzle.bit_index(x, y, z))
+}
+
+/// Trait for indexing into a block
+/// Mainly to support both printing a block as a BitCube, or as an array of bools
+pub trait BlockIndex<T> {
+    fn index(&self, puzzle: &Puzzle, i: T) -> bool;
+}
+
+impl BlockIndex<Coords> for BitCube {
+    fn index(&self, puzzle: &Puzzle, Coords(x, y, z): Coords) -> bool {
+        unpack_bit(puzzle, *self, x, y, z)
+    }
+}
+impl BlockIndex<Coords> for &[[[bool; 4]; 4]; 4] {
+    fn index(&self, _: &Puzzle, Coords(x, y, z): Coords) -> bool {
+        self[z][y][x]
+    }
+}
+
+/// Writing counterpart of `BlockIndex`, sets or clears a single cell of a block
+pub trait BlockIndexMut<T>: BlockIndex<T> {
+    fn set(&mut self, puzzle: &Puzzle, i: T, value: bool);
+}
+
+impl BlockIndexMut<Coords> for BitCube {
+    fn set(&mut self, puzzle: &Puzzle, Coords(x, y, z): Coords, value: bool) {
+        let bit = pack_bit(puzzle, true, x, y, z);
+        if value {
+            *self |= bit;
+        } else {
+            *self &= !bit;
+        }
+    }
+}
+
+pub fn print<T>(puzzle: &Puzzle, block: T)
+where
+    T: BlockIndex<Coords>,
+{
+    for y in 0..puzzle.dims[1] {
+        for z in 0..puzzle.dims[2] {
+            for x in 0..puzzle.dims[0] {
+                print!(
+                    "{}",
+                    if block.index(puzzle, Coords(x, y, z)) {
+                        "#"
+                    } else {
+                        "."
+                    }
+                );
+            }
+            print!("    ");
+        }
+        println!();
+    }
+}
+
+/// Print the shadows of a block along each axis side by side, like `print` prints its layers
+///
+/// The xy view has a row per y and a column per x, set where any z is set, the xz and yz
+/// views have a row per z and a column per x and y respectively.
+pub fn print_projections<T>(puzzle: &Puzzle, block: T)
+where
+    T: BlockIndex<Coords>,
+{
+    let [size_x, size_y, size_z] = puzzle.dims;
+    // Each view as rows of columns, true where the shadow falls
+    let mut views = [
+        vec![vec![false; size_x]; size_y],
+        vec![vec![false; size_x]; size_z],
+        vec![vec![false; size_y]; size_z],
+    ];
+    for z in 0..size_z {
+        for y in 0..size_y {
+            for x in 0..size_x {
+                if block.index(puzzle, Coords(x, y, z)) {
+                    views[0][y][x] = true;
+                    views[1][z][x] = true;
+                    views[2][z][y] = true;
+                }
+            }
+        }
+    }
+
+    println!("{:<width$}    {:<width$}    yz", "xy", "xz", width = size_x);
+    for row in 0..size_y.max(size_z) {
+        for (view, width) in views.iter().zip([size_x, size_x, size_y]) {
+            match view.get(row) {
+                Some(cells) => {
+                    for set in cells {
+                        print!("{}", if *set { "#" } else { "." });
+                    }
+                }
+                None => print!("{:width$}", ""),
+            }
+            print!("    ");
+        }
+        println!();
+    }
+}
+
+/// Label of the piece covering each cell, indexed by bit, None for empty cells
+pub fn solution_labels(puzzle: &Puzzle, pieces: &[Piece], picks: &Solution) -> Vec<Option<String>> {
+    let mut arr = vec![None; puzzle.num_bits()];
+
+    for (p, placement) in picks.0.iter().enumerate() {
+        let label = pieces[p].label();
+
+        for bit_index in 0..puzzle.num_bits() {
+            if placement.test_bit(bit_index) {
+                arr[bit_index] = Some(label.clone());
+            }
+        }
+    }
+    arr
+}
+
+/// Solution as a single line of the piece labels of the cells in bit order, see
+/// `Puzzle::bit_index`, with '.' for empty cells
+///
+/// 64 characters for the standard cube, labels are padded to `label_width` with more than
+/// 26 pieces. The solution isn't canonicalized, so rotations of a solution have different
+/// signatures, see `canonical_key` for that.
+pub fn solution_signature(puzzle: &Puzzle, solution: &Solution) -> String {
+    let width = label_width(solution.0.len());
+    (0..puzzle.num_bits())
+        .map(|bit_index| {
+            let label = solution
+                .0
+                .iter()
+                .position(|placement| placement.test_bit(bit_index))
+                .map_or_else(|| ".".to_string(), piece_label);
+            format!("{:<width$}", label)
+        })
+        .collect()
+}
+
+// Write a solution to stream, one line per z layer
+pub fn write_solution(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    picks: &Solution,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    write!(stream, "{}", picks.display(puzzle, pieces))
+}
+
+/// Write the label and name of every named piece as '#' comments, e.g. "# A: red cross"
+pub fn write_piece_names(
+    pieces: &[Piece],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    for piece in pieces {
+        if let Some(name) = &piece.name {
+            writeln!(stream, "# {}: {}", piece.label(), name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Background colors of the pieces in the 256 color ANSI palette, picked to be easy to tell apart
+pub const PIECE_COLORS: [u8; 13] = [196, 46, 21, 226, 201, 51, 208, 93, 118, 33, 213, 130, 250];
+
+/// Write a solution to stream with each piece on its own background color, for terminals
+///
+/// Unlike `write_solution`, the z layers are printed side by side like `print` does,
+/// one line per y. Empty cells are printed as '.'.
+pub fn write_solution_color(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    picks: &Solution,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let mut colors = vec![None; puzzle.num_bits()];
+    for (p, placement) in picks.0.iter().enumerate() {
+        for bit_index in 0..puzzle.num_bits() {
+            if placement.test_bit(bit_index) {
+                colors[bit_index] = Some(PIECE_COLORS[p % PIECE_COLORS.len()]);
+            }
+        }
+    }
+    let labels = solution_labels(puzzle, pieces, picks);
+    let width = label_width(pieces.len());
+
+    for y in 0..puzzle.dims[1] {
+        for z in 0..puzzle.dims[2] {
+            for x in 0..puzzle.dims[0] {
+                let bit_index = puzzle.bit_index(x, y, z);
+                match (&labels[bit_index], colors[bit_index]) {
+                    (Some(label), Some(color)) => {
+                        write!(stream, "\x1b[30;48;5;{}m{:<width$} \x1b[0m", color, label)?
+                    }
+                    _ => write!(stream, "{:<width$} ", ".")?,
+                }
+            }
+            write!(stream, "    ")?;
+        }
+        writeln!(stream)?;
+    }
+    Ok(())
+}
+
+/// Write one z layer of a solution to stream, one line per y, with the labels spaced like
+/// `write_solution` or in color like `write_solution_color` if `color` is set. Empty
+/// cells are written as '.'.
+pub fn write_solution_layer(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    picks: &Solution,
+    z: usize,
+    color: bool,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let labels = solution_labels(puzzle, pieces, picks);
+    let width = label_width(pieces.len());
+    for y in 0..puzzle.dims[1] {
+        for x in 0..puzzle.dims[0] {
+            let bit_index = puzzle.bit_index(x, y, z);
+            let piece = picks.0.iter().position(|p| p.test_bit(bit_index));
+            match (&labels[bit_index], piece) {
+                (Some(label), Some(piece)) if color => write!(
+                    stream,
+                    "\x1b[30;48;5;{}m{:<width$} \x1b[0m",
+                    PIECE_COLORS[piece % PIECE_COLORS.len()],
+                    label
+                )?,
+                (label, _) => {
+                    if width > 1 && x > 0 {
+                        write!(stream, " ")?;
+                    }
+                    let label = label.as_deref().unwrap_or(".");
+                    if x == puzzle.dims[0] - 1 {
+                        // No padding at the end of the line
+                        write!(stream, "{}", label)?;
+                    } else {
+                        write!(stream, "{:<width$}", label)?;
+                    }
+                }
+            }
+        }
+        writeln!(stream)?;
+    }
+    Ok(())
+}
+
+/// Write solutions to stream, each preceded by its index
+/// The names of the pieces are written first, if any piece is named
+pub fn write_solutions(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solutions: &[Solution],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    write_piece_names(pieces, stream)?;
+    for (i, solution) in solutions.iter().enumerate() {
+        writeln!(stream, "Solution #{}", i)?;
+        write_solution(puzzle, pieces, solution, stream)?;
+        writeln!(stream)?;
+    }
+    Ok(())
+}
+
+/// Write a solution to stream as JSON
+///
+/// The solution is an array of z layers, each an array of y rows, each an array of the
+/// piece labels along x, with null for empty cells.
+pub fn write_solution_json(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    picks: &Solution,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let arr = solution_labels(puzzle, pieces, picks);
+
+    write!(stream, "[")?;
+    for z in 0..puzzle.dims[2] {
+        if z != 0 {
+            write!(stream, ",")?;
+        }
+        write!(stream, "[")?;
+        for y in 0..puzzle.dims[1] {
+            if y != 0 {
+                write!(stream, ",")?;
+            }
+            write!(stream, "[")?;
+            for x in 0..puzzle.dims[0] {
+                if x != 0 {
+                    write!(stream, ",")?;
+                }
+                match &arr[puzzle.bit_index(x, y, z)] {
+                    Some(label) => write!(stream, "\"{}\"", label)?,
+                    None => write!(stream, "null")?,
+                }
+            }
+            write!(stream, "]")?;
+        }
+        write!(stream, "]")?;
+    }
+    write!(stream, "]")
+}
+
+/// Write solutions to stream as a JSON array, one solution per line
+pub fn write_solutions_json(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solutions: &[Solution],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    write!(stream, "[")?;
+    for (i, solution) in solutions.iter().enumerate() {
+        if i != 0 {
+            write!(stream, ",")?;
+        }
+        writeln!(stream)?;
+        write_solution_json(puzzle, pieces, solution, stream)?;
+    }
+    writeln!(stream)?;
+    writeln!(stream, "]")
+}
+
+/// Direction to the neighbouring cell sharing the face, and the corners of the face
+pub type Face = ((i32, i32, i32), [(usize, usize, usize); 4]);
+
+/// Faces of a unit cube, with corners counter-clockwise seen from outside
+pub const CUBE_FACES: [Face; 6] = [
+    ((-1, 0, 0), [(0, 0, 0), (0, 0, 1), (0, 1, 1), (0, 1, 0)]),
+    ((1, 0, 0), [(1, 0, 0), (1, 1, 0), (1, 1, 1), (1, 0, 1)]),
+    ((0, -1, 0), [(0, 0, 0), (1, 0, 0), (1, 0, 1), (0, 0, 1)]),
+    ((0, 1, 0), [(0, 1, 0), (0, 1, 1), (1, 1, 1), (1, 1, 0)]),
+    ((0, 0, -1), [(0, 0, 0), (0, 1, 0), (1, 1, 0), (1, 0, 0)]),
+    ((0, 0, 1), [(0, 0, 1), (1, 0, 1), (1, 1, 1), (0, 1, 1)]),
+];
+
+/// Write a solution to stream as a Wavefront OBJ mesh
+///
+/// Each piece is an object named after its label (pieceA, pieceB, ...), or its name with
+/// spaces replaced by '_', made of unit cubes. Faces between two cells of the same piece
+/// are left out.
+pub fn write_solution_obj(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    picks: &Solution,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let [size_x, size_y, size_z] = puzzle.dims.map(|d| d as i32);
+    // Vertex indices in OBJ files start at 1 and are shared by all objects
+    let mut num_vertices = 0;
+    for (p, placement) in picks.0.iter().enumerate() {
+        match &pieces[p].name {
+            Some(name) => writeln!(stream, "o {}", name.replace(char::is_whitespace, "_"))?,
+            None => writeln!(stream, "o piece{}", pieces[p].label())?,
+        }
+        for z in 0..puzzle.dims[2] {
+            for y in 0..puzzle.dims[1] {
+                for x in 0..puzzle.dims[0] {
+                    if !unpack_bit(puzzle, *placement, x, y, z) {
+                        continue;
+                    }
+                    for ((dx, dy, dz), corners) in CUBE_FACES {
+                        let (nx, ny, nz) = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+                        let inside = (0..size_x).contains(&nx)
+                            && (0..size_y).contains(&ny)
+                            && (0..size_z).contains(&nz);
+                        if inside
+                            && unpack_bit(puzzle, *placement, nx as usize, ny as usize, nz as usize)
+                        {
+                            continue;
+                        }
+                        for (cx, cy, cz) in corners {
+                            writeln!(stream, "v {} {} {}", x + cx, y + cy, z + cz)?;
+                        }
+                        writeln!(
+                            stream,
+                            "f {} {} {} {}",
+                            num_vertices + 1,
+                            num_vertices + 2,
+                            num_vertices + 3,
+                            num_vertices + 4
+                        )?;
+                        num_vertices += 4;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read solutions in the format written by `write_solutions`
+/// Lines before each "Solution #" header are skipped, such as '#' comments
+pub fn read_solutions(
+    puzzle: &Puzzle,
+    path: &std::path::Path,
+) -> Result<Vec<Solution>, std::io::Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut solutions = Vec::new();
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if !line.starts_with("Solution #") {
+            continue;
+        }
+        solutions.push(parse_solution_layers(
+            puzzle,
+            &mut lines,
+            &path.display().to_string(),
+        )?);
+    }
+    Ok(solutions)
+}
+
+/// Parse a single solution in the format written by `write_solution`, one line per z layer
+///
+/// Blank lines and '#' comments are skipped. Empty cells are '.', or '0' as written by
+/// earlier versions. `name` stands in for the path in error messages.
+pub fn parse_solution(puzzle: &Puzzle, text: &str, name: &str) -> Result<Solution, std::io::Error> {
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'));
+    let solution = parse_solution_layers(puzzle, &mut lines, name)?;
+    if let Some(line) = lines.next() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unexpected line after the last layer in {}: {}", name, line),
+        ));
+    }
+    Ok(solution)
+}
+
+/// Parse the z layers of a solution from the next lines, one line per layer
+fn parse_solution_layers<'a>(
+    puzzle: &Puzzle,
+    lines: &mut impl Iterator<Item = &'a str>,
+    name: &str,
+) -> Result<Solution, std::io::Error> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let labels: Vec<_> = (0..puzzle.num_pieces).map(piece_label).collect();
+    let width = label_width(puzzle.num_pieces);
+
+    let mut solution = Solution(vec![BitCube::default(); puzzle.num_pieces]);
+    for z in 0..puzzle.dims[2] {
+        let line = lines
+            .next()
+            .ok_or_else(|| invalid(format!("Truncated solution in {}", name)))?;
+        // One cell per character, or separated by spaces with multi-character labels
+        let rows: Vec<Vec<&str>> = if width == 1 {
+            line.split_whitespace()
+                .map(|row| {
+                    row.char_indices()
+                        .map(|(i, c)| &row[i..i + c.len_utf8()])
+                        .collect()
+                })
+                .collect()
+        } else {
+            let cells: Vec<_> = line.split_whitespace().collect();
+            cells
+                .chunks(puzzle.dims[0])
+                .map(|row| row.to_vec())
+                .collect()
+        };
+        for (y, row) in rows.iter().enumerate().take(puzzle.dims[1]) {
+            for (x, cell) in row.iter().enumerate().take(puzzle.dims[0]) {
+                // Empty cell, written as '0' by earlier versions
+                if *cell == "." || *cell == "0" {
+                    continue;
+                }
+                let Some(piece) = labels.iter().position(|label| label == cell) else {
+                    return Err(invalid(format!("Unexpected label '{}' in {}", cell, name)));
+                };
+                solution.0[piece] |= pack_bit(puzzle, true, x, y, z);
+            }
+        }
+    }
+    Ok(solution)
+}
+
+/// First bytes of a file written by `write_solutions_bin`
+const SOLUTIONS_BIN_MAGIC: &[u8; 4] = b"BCS1";
+
+/// Write solutions in a compact binary format, read back by `read_solutions_bin`
+///
+/// A header of the magic bytes "BCS1", the side lengths of the box as a byte each, the
+/// number of pieces as a u32 and the number of solutions as a u64 is followed by the
+/// placement of every piece in every solution as a u128, all little-endian.
+pub fn write_solutions_bin(
+    puzzle: &Puzzle,
+    solutions: &[Solution],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    stream.write_all(SOLUTIONS_BIN_MAGIC)?;
+    stream.write_all(&puzzle.dims.map(|d| d as u8))?;
+    stream.write_all(&(puzzle.num_pieces as u32).to_le_bytes())?;
+    stream.write_all(&(solutions.len() as u64).to_le_bytes())?;
+    for solution in solutions {
+        for placement in &solution.0 {
+            stream.write_all(&placement.0.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Read solutions written by `write_solutions_bin` for the same box and number of pieces
+pub fn read_solutions_bin(
+    puzzle: &Puzzle,
+    path: &std::path::Path,
+) -> Result<Vec<Solution>, std::io::Error> {
+    let invalid = |msg: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), msg),
+        )
+    };
+    let contents = std::fs::read(path)?;
+    let header_len = SOLUTIONS_BIN_MAGIC.len() + 3 + 4 + 8;
+    let Some((header, data)) = contents.split_at_checked(header_len) else {
+        return Err(invalid("truncated header"));
+    };
+    if !header.starts_with(SOLUTIONS_BIN_MAGIC) {
+        return Err(invalid("not a binary solution file"));
+    }
+    let dims = &header[4..7];
+    let num_pieces = u32::from_le_bytes(header[7..11].try_into().unwrap()) as usize;
+    let num_solutions = u64::from_le_bytes(header[11..19].try_into().unwrap()) as usize;
+    if dims
+        .iter()
+        .zip(puzzle.dims)
+        .any(|(d, size)| *d as usize != size)
+        || num_pieces != puzzle.num_pieces
+    {
+        return Err(invalid(&format!(
+            "solutions for {} pieces in a {}x{}x{} box, expected {} pieces in {}x{}x{}",
+            num_pieces,
+            dims[0],
+            dims[1],
+            dims[2],
+            puzzle.num_pieces,
+            puzzle.dims[0],
+            puzzle.dims[1],
+            puzzle.dims[2]
+        )));
+    }
+    if num_solutions.checked_mul(num_pieces * 16) != Some(data.len()) {
+        return Err(invalid("size doesn't match the number of solutions"));
+    }
+    let placements: Vec<BitCube> = data
+        .chunks_exact(16)
+        .map(|bytes| BitCube(u128::from_le_bytes(bytes.try_into().unwrap())))
+        .collect();
+    Ok((0..num_solutions)
+        .map(|i| Solution(placements[i * num_pieces..(i + 1) * num_pieces].to_vec()))
+        .collect())
+}
+
+/// Load all shard files in a directory and merge their solutions, in shard order
+pub fn merge_shards(puzzle: &Puzzle, dir: &str) -> Result<Vec<Solution>, std::io::Error> {
+    let mut shards = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let contents = std::fs::read_to_string(&path)?;
+        // Header: "# Shard <index>/<count>, ..."
+        let index = contents
+            .lines()
+            .next()
+            .and_then(|header| header.strip_prefix("# Shard "))
+            .and_then(|header| header.split('/').next())
+            .and_then(|index| index.parse::<usize>().ok());
+        if let Some(index) = index {
+            shards.push((index, path));
+        }
+    }
+    shards.sort();
+
+    let mut solutions = Vec::new();
+    for (_, path) in shards {
+        solutions.extend(read_solutions(puzzle, &path)?);
+    }
+    Ok(solutions)
+}
+
+/// Write a block as a Burr Tools voxel shape, cropped to its bounding box
+pub fn write_burr_tools_voxel(
+    puzzle: &Puzzle,
+    block: BitCube,
+    name: Option<&str>,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let (Coords(x0, y0, z0), Coords(x1, y1, z1)) = bounding_box(puzzle, block);
+    write!(
+        stream,
+        "<voxel x=\"{}\" y=\"{}\" z=\"{}\" type=\"0\"",
+        x1 - x0 + 1,
+        y1 - y0 + 1,
+        z1 - z0 + 1
+    )?;
+    if let Some(name) = name {
+        write!(stream, " name=\"{}\"", xml_escape(name))?;
+    }
+    write!(stream, ">")?;
+    for z in z0..=z1 {
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                write!(
+                    stream,
+                    "{}",
+                    if unpack_bit(puzzle, block, x, y, z) {
+                        '#'
+                    } else {
+                        '_'
+                    }
+                )?;
+            }
+        }
+    }
+    writeln!(stream, "</voxel>")
+}
+
+/// Text with the characters that are special in XML replaced by entities
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Cell (x, y, z) with signed coordinates, for turning shapes around the origin
+type Cell = (i32, i32, i32);
+
+/// Cell turned by transform `t` (0..24) of the Burr Tools cube grid, around the origin
+///
+/// Burr Tools numbers the rotations by `t % 4` quarter turns around x, followed by
+/// `t / 4` turning x to where it points: not at all, 1-3 quarter turns around y, or 1 or 3
+/// quarter turns around z. Quarter turns go by the right-hand rule.
+fn burr_tools_turn(cell: Cell, t: usize) -> Cell {
+    fn around_x((x, y, z): Cell) -> Cell {
+        (x, -z, y)
+    }
+    fn around_y((x, y, z): Cell) -> Cell {
+        (z, y, -x)
+    }
+    fn around_z((x, y, z): Cell) -> Cell {
+        (-y, x, z)
+    }
+    let (turn, times): (fn(Cell) -> Cell, usize) = match t / 4 {
+        quarter_turns @ 0..=3 => (around_y, quarter_turns),
+        4 => (around_z, 1),
+        _ => (around_z, 3),
+    };
+    let cell = (0..t % 4).fold(cell, |cell, _| around_x(cell));
+    (0..times).fold(cell, |cell, _| turn(cell))
+}
+
+/// Burr Tools transform and position of a placement of a piece, None if the placement
+/// isn't a rotation of the piece
+///
+/// Burr Tools turns the shape of the piece, as written by `write_burr_tools_voxel`, and
+/// moves it so that its hotspot lands on the position. The hotspot is the origin corner
+/// of the shape and turns with it, so after most transforms it isn't the corner of the
+/// placement.
+fn burr_tools_placement(
+    puzzle: &Puzzle,
+    piece: BitCube,
+    placement: BitCube,
+) -> Option<(usize, Coords)> {
+    // Cells relative to the corner of the bounding box
+    let relative = |block: BitCube| -> Vec<Cell> {
+        let (Coords(x0, y0, z0), _) = bounding_box(puzzle, block);
+        cells(puzzle, block)
+            .into_iter()
+            .map(|(x, y, z)| ((x - x0) as i32, (y - y0) as i32, (z - z0) as i32))
+            .collect()
+    };
+    let shape = relative(piece);
+    let mut target = relative(placement);
+    target.sort();
+    let (Coords(x, y, z), _) = bounding_box(puzzle, placement);
+    (0..24).find_map(|t| {
+        let turned: Vec<_> = shape.iter().map(|cell| burr_tools_turn(*cell, t)).collect();
+        let min = turned
+            .iter()
+            .fold((i32::MAX, i32::MAX, i32::MAX), |min, c| {
+                (min.0.min(c.0), min.1.min(c.1), min.2.min(c.2))
+            });
+        let mut moved: Vec<_> = turned
+            .iter()
+            .map(|c| (c.0 - min.0, c.1 - min.1, c.2 - min.2))
+            .collect();
+        moved.sort();
+        // The hotspot at the origin is -min from the corner of the turned shape
+        (moved == target).then(|| {
+            let hotspot = |corner: usize, min: i32| (corner as i32 - min) as usize;
+            (
+                t,
+                Coords(hotspot(x, min.0), hotspot(y, min.1), hotspot(z, min.2)),
+            )
+        })
+    })
+}
+
+/// Write the puzzle and its solutions in the Burr Tools XML format (.xmpuzzle)
+///
+/// Shapes 0..pieces.len() are the pieces, followed by the full cube as the result shape.
+/// Each solution is an assembly with an "x y z transform" group per piece, where
+/// (x, y, z) is where the hotspot of the piece goes and the transform is the Burr Tools
+/// rotation, see `burr_tools_placement`. Names are escaped for XML. A placement that isn't
+/// a rotation of its piece is an `InvalidData` error.
+pub fn write_burr_tools(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solutions: &[Solution],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    writeln!(stream, "<?xml version=\"1.0\"?>")?;
+    writeln!(stream, "<puzzle version=\"2\">")?;
+    writeln!(stream, "<gridType type=\"0\"/>")?;
+    writeln!(stream, "<colors/>")?;
+    writeln!(stream, "<shapes>")?;
+    for piece in pieces {
+        write_burr_tools_voxel(puzzle, piece.bits, piece.name.as_deref(), stream)?;
+    }
+    write_burr_tools_voxel(puzzle, puzzle.mask(), None, stream)?;
+    writeln!(stream, "</shapes>")?;
+
+    writeln!(stream, "<problems>")?;
+    writeln!(stream, "<problem>")?;
+    writeln!(stream, "<shapes>")?;
+    for p in 0..pieces.len() {
+        writeln!(stream, "<shape id=\"{}\" count=\"1\"/>", p)?;
+    }
+    writeln!(stream, "</shapes>")?;
+    writeln!(stream, "<result id=\"{}\"/>", pieces.len())?;
+    writeln!(stream, "<bitmap/>")?;
+    writeln!(stream, "<solutions>")?;
+    for (i, solution) in solutions.iter().enumerate() {
+        write!(stream, "<solution><assembly>")?;
+        for (p, placement) in solution.0.iter().enumerate() {
+            let (transform, Coords(x, y, z)) =
+                burr_tools_placement(puzzle, pieces[p].bits, *placement).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Solution #{}: the placement of piece {} is not a rotation of it",
+                            i,
+                            pieces[p].display_name()
+                        ),
+                    )
+                })?;
+            if p != 0 {
+                write!(stream, " ")?;
+            }
+            write!(stream, "{} {} {} {}", x, y, z, transform)?;
+        }
+        writeln!(stream, "</assembly></solution>")?;
+    }
+    writeln!(stream, "</solutions>")?;
+    writeln!(stream, "</problem>")?;
+    writeln!(stream, "</problems>")?;
+    writeln!(stream, "<comment/>")?;
+    writeln!(stream, "</puzzle>")?;
+    Ok(())
+}
+
+/// Error with the name of the file or stream in the message
+fn named_error(name: &str, err: std::io::Error) -> Error {
+    Error::Io(std::io::Error::new(
+        err.kind(),
+        format!("{}: {}", name, err),
+    ))
+}
+
+/// Read a stream to a string, with `name` in the error message
+fn read_all(mut reader: impl std::io::Read, name: &str) -> Result<String, Error> {
+    let mut contents = String::new();
+    reader
+        .read_to_string(&mut contents)
+        .map_err(|err| named_error(name, err))?;
+    Ok(contents)
+}
+
+/// Read pieces from file
+///
+/// File format:
+/// WxHxL blocks for a box W wide and H high (NxNxL for a cube of size N), each piece
+/// starting with a piece id (0, 1, 2, ...) and optionally a name for the piece after the id,
+/// e.g. "# 0 red cross". A piece that must keep the orientation it is given in has the word
+/// "fixed" right after its id, e.g. "# 3 fixed" or "# 3 fixed blue hook". Blank lines, trailing whitespace and lines starting with '#' that
+/// aren't piece headers are skipped. A file can hold several puzzles, see `read_puzzles_from`.
+/// The number of layers L is taken from the number of rows of each piece, so it can differ
+/// between pieces and be anything up to the depth of the box. The pieces of the standard
+/// set have 2 layers.
+/// For the standard 4x4x4 cube:
+/// z y x: 0123
+/// 0 0    0000
+/// 0 1    0000
+/// 0 2    0000
+/// 0 3    0000
+/// 1 0    0000
+/// 1 1    0000
+/// 1 2    0000
+/// 1 3    0000
+///
+/// E.g.:
+/// # 0
+/// 0100
+/// 1110
+/// 0100
+/// 0000
+/// 0000
+/// 0000
+/// 0000
+/// 0000
+/// # 1
+/// ...
+///
+/// A file without pieces, or that ends in the middle of a layer, is a parse error.
+pub fn read_pieces(puzzle: &Puzzle, path: &str) -> Result<Vec<Piece>, Error> {
+    let file = std::fs::File::open(path).map_err(|err| named_error(path, err))?;
+    read_pieces_from(puzzle, file, path)
+}
+
+/// Read pieces in the format of `read_pieces` from any reader, such as stdin
+/// `name` stands in for the path in error messages. For a file with several puzzles, see
+/// `read_puzzles_from`, these are the pieces of the first one.
+pub fn read_pieces_from(
+    puzzle: &Puzzle,
+    reader: impl std::io::Read,
+    name: &str,
+) -> Result<Vec<Piece>, Error> {
+    let mut puzzles = read_puzzles_from(puzzle, reader, name, GridChars::default())?;
+    Ok(puzzles.swap_remove(0).1)
+}
+
+/// Name of a puzzle in a piece file, None if it has none, and its pieces
+pub type NamedPieces = (Option<String>, Vec<Piece>);
+
+/// Read a file with several puzzles, see `read_puzzles_from`
+pub fn read_puzzles(
+    puzzle: &Puzzle,
+    path: &str,
+    chars: GridChars,
+) -> Result<Vec<NamedPieces>, Error> {
+    let file = std::fs::File::open(path).map_err(|err| named_error(path, err))?;
+    read_puzzles_from(puzzle, file, path, chars)
+}
+
+/// Read the pieces of several puzzles from any reader, each puzzle in the format of
+/// `read_pieces` and starting with a "=== <name> ===" line, e.g. "=== soma ==="
+///
+/// Returns the name and the pieces of each puzzle, in the order of the file. Pieces before
+/// the first name line are a puzzle without a name, so a file in the format of
+/// `read_pieces` is a single puzzle. The pieces of each puzzle are numbered from 0, and
+/// every puzzle is read for the dimensions of `puzzle`, with the cells of the grids
+/// written as `chars`.
+pub fn read_puzzles_from(
+    puzzle: &Puzzle,
+    reader: impl std::io::Read,
+    name: &str,
+    chars: GridChars,
+) -> Result<Vec<NamedPieces>, Error> {
+    let contents = read_all(reader, name)?;
+
+    // The lines of each puzzle with their line numbers, starting at 1
+    let mut sections = vec![(None, Vec::new())];
+    for (i, line) in contents.lines().enumerate() {
+        match puzzle_name(line) {
+            Some(puzzle_name) => sections.push((Some(puzzle_name.to_string()), Vec::new())),
+            None => sections.last_mut().unwrap().1.push((i + 1, line)),
+        }
+    }
+    // Only comments before the first named puzzle
+    if sections.len() > 1
+        && sections[0]
+            .1
+            .iter()
+            .all(|(_, line)| is_skipped_line(line, chars))
+    {
+        sections.remove(0);
+    }
+
+    let last = sections.len() - 1;
+    sections
+        .into_iter()
+        .enumerate()
+        .map(|(i, (puzzle_name, lines))| {
+            let context = match &puzzle_name {
+                Some(puzzle_name) => format!("puzzle {}: ", puzzle_name),
+                None => String::new(),
+            };
+            let end = if i == last { "file" } else { "puzzle" };
+            let pieces = parse_pieces(puzzle, &lines, name, &context, end, chars)?;
+            Ok((puzzle_name, pieces))
+        })
+        .collect()
+}
+
+/// Name of the puzzle if the line starts one, "=== <name> ==="
+fn puzzle_name(line: &str) -> Option<&str> {
+    let name = line.trim().strip_prefix("===")?.strip_suffix("===")?.trim();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Parse the pieces of one puzzle, given as lines with their line numbers
+/// `name` stands in for the path in error messages, `context` is put before the rest of
+/// the message and `end` is what ends after the lines, the file or the puzzle.
+fn parse_pieces(
+    puzzle: &Puzzle,
+    lines: &[(usize, &str)],
+    name: &str,
+    context: &str,
+    end: &str,
+    chars: GridChars,
+) -> Result<Vec<Piece>, Error> {
+    // Each piece is the text from its header up to the next header, with the line number
+    // of the header
+    let mut texts: Vec<(usize, String)> = Vec::new();
+    for &(number, line) in lines {
+        if is_piece_header(line) {
+            texts.push((number, String::new()));
+        }
+        match texts.last_mut() {
+            Some((_, text)) => {
+                text.push_str(line);
+                text.push('\n');
+            }
+            None if is_skipped_line(line, chars) => {}
+            None => {
+                return Err(Error::Parse(format!(
+                    "{}:{}: {}expected a piece header like \"# 0\"",
+                    name, number, context
+                )))
+            }
+        }
+    }
+
+    if texts.is_empty() {
+        return Err(Error::Parse(format!(
+            "{}: {}no pieces, expected a piece header like \"# 0\"",
+            name, context
+        )));
+    }
+
+    let mut pieces = Vec::new();
+    for (first_line, text) in texts {
+        let mut piece = Piece::parse_with(puzzle, &text, chars).map_err(|err| {
+            // An error past the end of the text means the file ends in this piece
+            let truncated = if err.line > text.lines().count() {
+                format!(", the {} ends after {} complete pieces", end, pieces.len())
+            } else {
+                String::new()
+            };
+            Error::Parse(format!(
+                "{}:{}:{}: {}piece {}: {}{}",
+                name,
+                first_line + err.line - 1,
+                err.column,
+                context,
+                pieces.len(),
+                err.message,
+                truncated
+            ))
+        })?;
+        piece.id = pieces.len();
+        pieces.push(piece);
+    }
+    Ok(pieces)
+}
+
+/// Whether the line is a piece header, "# <id> [name]"
+fn is_piece_header(line: &str) -> bool {
+    line.strip_prefix('#')
+        .and_then(|rest| rest.split_whitespace().next())
+        .is_some_and(|id| id.parse::<usize>().is_ok())
+}
+
+/// Whether the line is blank or a comment, a line starting with '#' that isn't a header
+///
+/// With '#' as one of the grid characters, a line of only grid characters is a row of a
+/// piece rather than a comment.
+fn is_skipped_line(line: &str, chars: GridChars) -> bool {
+    let line = line.trim_end();
+    line.is_empty() || (line.starts_with('#') && !is_piece_header(line) && !chars.is_row(line))
+}
+
+/// Characters of the filled and empty cells in the grids of a piece file, '1' and '0' by
+/// default
+///
+/// Parsed from the two characters, filled first, e.g. "#." for grids of '#' and '.'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridChars {
+    pub filled: char,
+    pub empty: char,
+}
+
+impl Default for GridChars {
+    fn default() -> Self {
+        Self {
+            filled: '1',
+            empty: '0',
+        }
+    }
+}
+
+impl GridChars {
+    /// Whether the character is a filled or an empty cell
+    fn is_cell(&self, c: char) -> bool {
+        c == self.filled || c == self.empty
+    }
+    /// Whether the line only has grid characters
+    fn is_row(&self, line: &str) -> bool {
+        line.chars().all(|c| self.is_cell(c))
+    }
+}
+
+impl std::str::FromStr for GridChars {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.chars().collect::<Vec<_>>()[..] {
+            [filled, empty]
+                if filled != empty && !filled.is_whitespace() && !empty.is_whitespace() =>
+            {
+                Ok(Self { filled, empty })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Position in a piece's text where it can't be parsed, see `Piece::parse`
+#[derive(Debug, PartialEq)]
+pub struct PieceParseError {
+    /// Line and column of the text, starting at 1
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for PieceParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PieceParseError {}
+
+impl Piece {
+    /// Parse a piece in the format of `read_pieces`: an optional "# <id> [fixed] [name]"
+    /// header followed by the grid of the piece, for a cube or box with the given dimensions
+    ///
+    /// The id is taken from the header, 0 if there is none.
+    pub fn parse(puzzle: &Puzzle, text: &str) -> Result<Self, PieceParseError> {
+        Self::parse_with(puzzle, text, GridChars::default())
+    }
+
+    /// Parse a piece like `parse`, with the cells of the grid written as `chars`
+    ///
+    /// Any other character in a row is an error, rather than an empty cell.
+    pub fn parse_with(
+        puzzle: &Puzzle,
+        text: &str,
+        chars: GridChars,
+    ) -> Result<Self, PieceParseError> {
+        let error = |line: usize, column: usize, message: String| PieceParseError {
+            line,
+            column,
+            message,
+        };
+        // Line numbers start at 1
+        let mut lines = text
+            .lines()
+            .map(str::trim_end)
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .filter(|(_, line)| !is_skipped_line(line, chars))
+            .peekable();
+
+        let (mut id, mut name, mut fixed) = (0, None, false);
+        // Line of the header or the last row read, a missing row is reported after it
+        let mut last_line = 0;
+        if let Some((number, header)) = lines.next_if(|(_, line)| is_piece_header(line)) {
+            last_line = number;
+            let mut words = header
+                .trim_start_matches('#')
+                .trim()
+                .splitn(2, char::is_whitespace);
+            id = words.next().and_then(|id| id.parse().ok()).unwrap_or(0);
+            let mut rest = words.next().unwrap_or("").trim();
+            if let Some(after) = rest.strip_prefix("fixed") {
+                if after.is_empty() || after.starts_with(char::is_whitespace) {
+                    fixed = true;
+                    rest = after.trim();
+                }
+            }
+            // The name is everything after the id and the fixed flag
+            name = (!rest.is_empty()).then(|| rest.to_string());
+        }
+
+        // The number of layers is given by the number of rows, up to the depth of the box
+        let rows: Vec<_> =
+            std::iter::from_fn(|| lines.next_if(|(_, line)| !is_piece_header(line))).collect();
+        let max_rows = puzzle.dims[1] * puzzle.dims[2];
+        if let Some((number, _)) = rows.get(max_rows) {
+            return Err(error(
+                *number,
+                1,
+                "unexpected line after the last row".to_string(),
+            ));
+        }
+        if rows.is_empty() || rows.len() % puzzle.dims[1] != 0 {
+            let last_line = rows.last().map_or(last_line, |(number, _)| *number);
+            return Err(error(
+                last_line + 1,
+                1,
+                "truncated, expected another row".to_string(),
+            ));
+        }
+
+        let mut bits = BitCube::default();
+        for (i, &(number, line)) in rows.iter().enumerate() {
+            let (z, y) = (i / puzzle.dims[1], i % puzzle.dims[1]);
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    c if chars.is_cell(c) && x < puzzle.dims[0] => {
+                        if c == chars.filled {
+                            bits |= pack_bit(puzzle, true, x, y, z);
+                        }
+                    }
+                    c if chars.is_cell(c) => {
+                        return Err(error(
+                            number,
+                            x + 1,
+                            format!("expected {} cells in a row", puzzle.dims[0]),
+                        ))
+                    }
+                    c => {
+                        return Err(error(
+                            number,
+                            x + 1,
+                            format!(
+                                "unexpected '{}', expected '{}' or '{}'",
+                                c, chars.empty, chars.filled
+                            ),
+                        ))
+                    }
+                }
+            }
+            if line.chars().count() < puzzle.dims[0] {
+                return Err(error(
+                    number,
+                    line.chars().count() + 1,
+                    format!("expected {} cells in a row", puzzle.dims[0]),
+                ));
+            }
+        }
+        if let Some((number, _)) = lines.next() {
+            return Err(error(
+                number,
+                1,
+                "unexpected line after the last row".to_string(),
+            ));
+        }
+        Ok(Piece {
+            id,
+            name,
+            bits,
+            fixed,
+        })
+    }
+}
+
+/// Parses a piece of the standard 4x4x4 cube, see `Piece::parse`
+impl std::str::FromStr for Piece {
+    type Err = PieceParseError;
+    fn from_str(s: &str) -> Result<Self, PieceParseError> {
+        Piece::parse(&Puzzle::new(4, 1), s)
+    }
+}
+
+/// Value of a parsed JSON document
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Value of the given key, if this is an object with that key
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a JSON document
+pub fn parse_json(text: &str) -> Result<Json, String> {
+    let mut parser = JsonParser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if let Some(c) = parser.chars.next() {
+        return Err(format!("Unexpected '{}' after the end of the document", c));
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected '{}', got '{}'", expected, c)),
+            None => Err(format!("Expected '{}', got end of document", expected)),
+        }
+    }
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('[') => {
+                self.chars.next();
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Json::Array(items)),
+                        _ => return Err("Expected ',' or ']' in array".to_string()),
+                    }
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = match self.value()? {
+                        Json::String(key) => key,
+                        _ => return Err("Expected a string key in object".to_string()),
+                    };
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Json::Object(fields)),
+                        _ => return Err("Expected ',' or '}' in object".to_string()),
+                    }
+                }
+            }
+            Some('"') => {
+                self.chars.next();
+                let mut string = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('"') => return Ok(Json::String(string)),
+                        Some('\\') => match self.chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some('b') => string.push('\u{8}'),
+                            Some('f') => string.push('\u{c}'),
+                            Some('u') => {
+                                let hex: String =
+                                    (0..4).filter_map(|_| self.chars.next()).collect();
+                                let c = u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| format!("Invalid escape \\u{}", hex))?;
+                                string.push(c);
+                            }
+                            Some(c) => string.push(c),
+                            None => return Err("Unterminated string".to_string()),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err("Unterminated string".to_string()),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                }
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("Invalid number {}", number))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => Err(format!("Unexpected '{}'", word)),
+                }
+            }
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Err("Unexpected end of document".to_string()),
+        }
+    }
+}
+
+/// Read pieces from a JSON file
+///
+/// The file is an array with one entry per piece, either an array of the filled cells
+/// as `[x, y, z]` coordinates, or an object with the cells in `"cells"`, an optional
+/// `"name"` and an optional `"fixed": true` for a piece that can't be rotated. E.g.:
+/// [
+///   {"name": "red cross", "cells": [[1, 0, 0], [0, 1, 0], [1, 1, 0], [2, 1, 0], [1, 2, 0]]},
+///   [[0, 0, 0], [0, 1, 0], [1, 1, 0], [1, 1, 1], [1, 2, 1]]
+/// ]
+pub fn read_pieces_json(puzzle: &Puzzle, path: &str) -> Result<Vec<Piece>, Error> {
+    let file = std::fs::File::open(path).map_err(|err| named_error(path, err))?;
+    read_pieces_json_from(puzzle, file, path)
+}
+
+/// Read pieces in the format of `read_pieces_json` from any reader, such as stdin
+/// `name` stands in for the path in error messages.
+pub fn read_pieces_json_from(
+    puzzle: &Puzzle,
+    reader: impl std::io::Read,
+    name: &str,
+) -> Result<Vec<Piece>, Error> {
+    let invalid = |msg: String| Error::Parse(format!("{}: {}", name, msg));
+    let contents = read_all(reader, name)?;
+    let Json::Array(entries) = parse_json(&contents).map_err(invalid)? else {
+        return Err(invalid("Expected an array of pieces".to_string()));
+    };
+
+    let mut pieces = Vec::new();
+    for entry in &entries {
+        let id = pieces.len();
+        let (name, cells, fixed) = match entry {
+            Json::Object(_) => {
+                let name = match entry.get("name") {
+                    Some(Json::String(name)) => Some(name.clone()),
+                    None | Some(Json::Null) => None,
+                    Some(_) => return Err(invalid(format!("Piece {}: name is not a string", id))),
+                };
+                let fixed = match entry.get("fixed") {
+                    Some(Json::Bool(fixed)) => *fixed,
+                    None | Some(Json::Null) => false,
+                    Some(_) => return Err(invalid(format!("Piece {}: fixed is not a bool", id))),
+                };
+                (name, entry.get("cells"), fixed)
+            }
+            _ => (None, Some(entry), false),
+        };
+        let Some(Json::Array(cells)) = cells else {
+            return Err(invalid(format!("Piece {}: expected an array of cells", id)));
+        };
+
+        let mut bits = BitCube::default();
+        for cell in cells {
+            let coords = match cell {
+                Json::Array(coords) if coords.len() == 3 => coords
+                    .iter()
+                    .zip(puzzle.dims)
+                    .map(|(c, size)| match c {
+                        Json::Number(c) if c.fract() == 0.0 && (0.0..size as f64).contains(c) => {
+                            Some(*c as usize)
+                        }
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>(),
+                _ => None,
+            };
+            let Some(coords) = coords else {
+                return Err(invalid(format!(
+                    "Piece {}: expected cells as [x, y, z] within the {}x{}x{} box",
+                    id, puzzle.dims[0], puzzle.dims[1], puzzle.dims[2]
+                )));
+            };
+            bits |= pack_bit(puzzle, true, coords[0], coords[1], coords[2]);
+        }
+        pieces.push(Piece {
+            id,
+            name,
+            bits,
+            fixed,
+        });
+    }
+    Ok(pieces)
+}
+
+/// Side lengths of a box along with a cell in it
+type RotatedCell = ([usize; 3], (usize, usize, usize));
+
+/// Cell of a box with side lengths `dims` after rotating the box by 90 degrees around
+/// the given axis, returned with the side lengths of the rotated box
+fn rotate_cell(dims: [usize; 3], (x, y, z): (usize, usize, usize), axis: Axis) -> RotatedCell {
+    match axis {
+        Axis::X => ([dims[0], dims[2], dims[1]], (x, z, dims[1] - 1 - y)),
+        Axis::Y => ([dims[2], dims[1], dims[0]], (z, y, dims[0] - 1 - x)),
+        Axis::Z => ([dims[1], dims[0], dims[2]], (y, dims[0] - 1 - x, z)),
+    }
+}
+
+/// Cell of the box after rotating the box into orientation `nr`, see `orient_piece`
+fn orient_cell(dims: [usize; 3], cell: (usize, usize, usize), nr: usize) -> RotatedCell {
+    let mut rotated = (dims, cell);
+    for _ in 0..nr % 4 {
+        rotated = rotate_cell(rotated.0, rotated.1, Axis::X);
+    }
+    let (axis, turns) = match nr / 4 {
+        0 => (Axis::X, 0),
+        1 => (Axis::Y, 1),
+        2 => (Axis::Y, 2),
+        3 => (Axis::Y, 3),
+        4 => (Axis::Z, 1),
+        _ => (Axis::Z, 3),
+    };
+    for _ in 0..turns {
+        rotated = rotate_cell(rotated.0, rotated.1, axis);
+    }
+    rotated
+}
+
+/// Cells (x, y, z) of the set bits in a block
+fn cells(puzzle: &Puzzle, block: BitCube) -> Vec<(usize, usize, usize)> {
+    let mut cells = Vec::new();
+    for z in 0..puzzle.dims[2] {
+        for y in 0..puzzle.dims[1] {
+            for x in 0..puzzle.dims[0] {
+                if unpack_bit(puzzle, block, x, y, z) {
+                    cells.push((x, y, z));
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// Block with the given cells, leaving out the cells outside of the box
+fn pack_cells(puzzle: &Puzzle, cells: impl IntoIterator<Item = (usize, usize, usize)>) -> BitCube {
+    let [size_x, size_y, size_z] = puzzle.dims;
+    cells
+        .into_iter()
+        .filter(|&(x, y, z)| x < size_x && y < size_y && z < size_z)
+        .fold(BitCube::default(), |block, (x, y, z)| {
+            block | pack_bit(puzzle, true, x, y, z)
+        })
+}
+
+/// Rotate piece by 90 degres around the given axis
+///
+/// The box is rotated in place, cells that end up outside of a box that isn't a cube
+/// are lost. Looks up where each bit goes in the tables of the puzzle, see
+/// `rotate_piece_90_by_cells` for the rotation they are built from.
+pub fn rotate_piece_90(puzzle: &Puzzle, piece: BitCube, axis: Axis) -> BitCube {
+    Puzzle::permute_bits(&puzzle.rotations[axis as usize], piece)
+}
+
+/// Rotate piece by 90 degres around the given axis, cell by cell
+///
+/// Gives the same result as `rotate_piece_90`, which the tests compare it with.
+pub fn rotate_piece_90_by_cells(puzzle: &Puzzle, piece: BitCube, axis: Axis) -> BitCube {
+    pack_cells(
+        puzzle,
+        cells(puzzle, piece)
+            .into_iter()
+            .map(|cell| rotate_cell(puzzle.dims, cell, axis).1),
+    )
+}
+
+/// Reflect the piece through the plane x = size / 2, taking x to size - 1 - x
+pub fn mirror_piece(puzzle: &Puzzle, piece: BitCube) -> BitCube {
+    let last = puzzle.dims[0] - 1;
+    let mut new_piece = BitCube::default();
+    for z in 0..puzzle.dims[2] {
+        for y in 0..puzzle.dims[1] {
+            for x in 0..puzzle.dims[0] {
+                new_piece.set(
+                    puzzle,
+                    Coords(x, y, z),
+                    piece.index(puzzle, Coords(last - x, y, z)),
+                );
+            }
+        }
+    }
+    new_piece
+}
+
+/// Translate the piece in the box by dx, dy, dz
+pub fn translate(puzzle: &Puzzle, piece: BitCube, dx: i32, dy: i32, dz: i32) -> BitCube {
+    let [size_x, size_y, size_z] = puzzle.dims.map(|d| d as i32);
+    let mut new_piece = BitCube::default();
+    for z in 0..size_z {
+        for y in 0..size_y {
+            for x in 0..size_x {
+                let sx = x + dx;
+                let sy = y + dy;
+                let sz = z + dz;
+                if sx < size_x && sy < size_y && sz < size_z && sx >= 0 && sy >= 0 && sz >= 0 {
+                    new_piece.set(
+                        puzzle,
+                        Coords(sx as usize, sy as usize, sz as usize),
+                        piece.index(puzzle, Coords(x as usize, y as usize, z as usize)),
+                    );
+                }
+            }
+        }
+    }
+    new_piece
+}
+
+/// Bounding box of the set bits in a block, as inclusive (min, max) corners
+pub fn bounding_box(puzzle: &Puzzle, block: BitCube) -> (Coords, Coords) {
+    let [size_x, size_y, size_z] = puzzle.dims;
+    let (mut min, mut max) = ((size_x - 1, size_y - 1, size_z - 1), (0, 0, 0));
+    for z in 0..puzzle.dims[2] {
+        for y in 0..puzzle.dims[1] {
+            for x in 0..puzzle.dims[0] {
+                if unpack_bit(puzzle, block, x, y, z) {
+                    min = (min.0.min(x), min.1.min(y), min.2.min(z));
+                    max = (max.0.max(x), max.1.max(y), max.2.max(z));
+                }
+            }
+        }
+    }
+    (Coords(min.0, min.1, min.2), Coords(max.0, max.1, max.2))
+}
+
+/// Translate the piece so that its bounding box starts at the origin
+pub fn normalize(puzzle: &Puzzle, piece: BitCube) -> BitCube {
+    let (Coords(x, y, z), _) = bounding_box(puzzle, piece);
+    translate(puzzle, piece, -(x as i32), -(y as i32), -(z as i32))
+}
+
+/// Rotate piece into orientation `nr` (0..24)
+///
+/// The piece is first rotated `nr % 4` times around X, after which `nr / 4` picks
+/// which way the X axis points: unchanged, Y 1-3 times or Z 1 or 3 times. As with
+/// `rotate_piece_90` the box is rotated in place, so only the orientations in
+/// `Puzzle::symmetries` keep all cells of a box that isn't a cube.
+pub fn orient_piece(puzzle: &Puzzle, piece: BitCube, nr: usize) -> BitCube {
+    Puzzle::permute_bits(&puzzle.orientations[nr], piece)
+}
+
+/// Rotate piece into orientation `nr` (0..24), cell by cell
+///
+/// Gives the same result as `orient_piece`, which the tests compare it with.
+pub fn orient_piece_by_cells(puzzle: &Puzzle, piece: BitCube, nr: usize) -> BitCube {
+    pack_cells(
+        puzzle,
+        cells(puzzle, piece)
+            .into_iter()
+            .map(|cell| orient_cell(puzzle.dims, cell, nr).1),
+    )
+}
+
+/// Rotate piece into orientation `nr` (0..24) and move it to the origin
+///
+/// Unlike `normalize(orient_piece(..))` no cells are lost as long as the piece fits the
+/// box in the new orientation.
+pub fn orient_normalized(puzzle: &Puzzle, piece: BitCube, nr: usize) -> BitCube {
+    let rotated: Vec<_> = cells(puzzle, piece)
+        .into_iter()
+        .map(|cell| orient_cell(puzzle.dims, cell, nr).1)
+        .collect();
+    let min = rotated
+        .iter()
+        .fold((usize::MAX, usize::MAX, usize::MAX), |min, c| {
+            (min.0.min(c.0), min.1.min(c.1), min.2.min(c.2))
+        });
+    pack_cells(
+        puzzle,
+        rotated
+            .into_iter()
+            .map(|(x, y, z)| (x - min.0, y - min.1, z - min.2)),
+    )
+}
+
+/// Canonical form of a piece, the same for all rotations and translations of the piece
+///
+/// This is the smallest of the orientations of the piece that fit the box, each moved
+/// to the origin.
+pub fn canonical_piece(puzzle: &Puzzle, piece: BitCube) -> BitCube {
+    (0..24)
+        .map(|nr| orient_normalized(puzzle, piece, nr))
+        .filter(|oriented| oriented.count_ones() == piece.count_ones())
+        .min()
+        .unwrap()
+}
+
+/// Permutation of the cells of the box, such as a rotation or a reflection
+///
+/// Maps every bit to the bit it moves to, `LOST_BIT` for cells that end up outside of a
+/// box that isn't a cube. Transforms compose with `then`, so symmetries can be built from
+/// the rotations and the reflection.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    table: [u8; MAX_CELLS],
+}
+
+impl Transform {
+    /// Transform that leaves every cell in place
+    pub fn identity() -> Self {
+        let mut table = [LOST_BIT; MAX_CELLS];
+        for (bit, target) in table.iter_mut().enumerate() {
+            *target = bit as u8;
+        }
+        Self { table }
+    }
+    /// Bit that `bit` moves to, None if it leaves the box
+    pub fn cell(&self, bit: usize) -> Option<usize> {
+        let target = self.table[bit];
+        (target != LOST_BIT).then_some(target as usize)
+    }
+    /// Move every set bit of the block, dropping the cells that leave the box
+    pub fn apply(&self, block: BitCube) -> BitCube {
+        Puzzle::permute_bits(&self.table, block)
+    }
+    /// This transform followed by `other`
+    pub fn then(&self, other: &Transform) -> Transform {
+        let mut table = [LOST_BIT; MAX_CELLS];
+        for (bit, target) in table.iter_mut().enumerate() {
+            if self.table[bit] != LOST_BIT {
+                *target = other.table[self.table[bit] as usize];
+            }
+        }
+        Transform { table }
+    }
+    /// Whether the transform maps the `num_bits` cells of the box onto themselves
+    pub fn is_permutation(&self, num_bits: usize) -> bool {
+        let mut seen = BitCube::default();
+        for bit in 0..num_bits {
+            match self.cell(bit) {
+                Some(target) if target < num_bits && !seen.test_bit(target) => seen.set_bit(target),
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// The 24 rotations of the box, transform `nr` rotates like `orient_piece` with that `nr`
+///
+/// Only the ones in `Puzzle::symmetries` keep all cells of a box that isn't a cube.
+pub fn cube_orientations(puzzle: &Puzzle) -> [Transform; 24] {
+    std::array::from_fn(|nr| Transform {
+        table: puzzle.orientations[nr],
+    })
+}
+
+/// Reflection of the box through the plane x = size / 2, like `mirror_piece`
+pub fn reflection(puzzle: &Puzzle) -> Transform {
+    let mut table = [LOST_BIT; MAX_CELLS];
+    let last = puzzle.dims[0] - 1;
+    for x in 0..puzzle.dims[0] {
+        for y in 0..puzzle.dims[1] {
+            for z in 0..puzzle.dims[2] {
+                table[puzzle.bit_index(x, y, z)] = puzzle.bit_index(last - x, y, z) as u8;
+            }
+        }
+    }
+    Transform { table }
+}
+
+/// The 24 rotations of `cube_orientations` followed by the same rotations after the
+/// reflection, numbered like the `nr` of `transform_solution`
+pub fn cube_symmetries(puzzle: &Puzzle) -> [Transform; 48] {
+    let rotations = cube_orientations(puzzle);
+    let reflection = reflection(puzzle);
+    std::array::from_fn(|nr| {
+        if nr < 24 {
+            rotations[nr]
+        } else {
+            reflection.then(&rotations[nr - 24])
+        }
+    })
+}
+
+/// Reason a piece can't be used
+#[derive(Debug, PartialEq)]
+pub enum PieceError {
+    /// The piece has no cells
+    Empty,
+    /// The cells of the piece are split into this many face-connected parts
+    Disconnected(usize),
+}
+
+impl std::fmt::Display for PieceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PieceError::Empty => write!(f, "piece has no cells"),
+            PieceError::Disconnected(parts) => {
+                write!(f, "piece is split into {} disconnected parts", parts)
+            }
+        }
+    }
+}
+
+/// Check that a piece has cells and that they are all face-connected
+pub fn validate_piece(puzzle: &Puzzle, piece: BitCube) -> Result<(), PieceError> {
+    if piece.is_empty() {
+        return Err(PieceError::Empty);
+    }
+    let parts = connected_components(puzzle, piece).len();
+    if parts > 1 {
+        return Err(PieceError::Disconnected(parts));
+    }
+    Ok(())
+}
+
+/// Most pieces the search can track, used pieces are the bits of a u64
+pub const MAX_PIECES: usize = 64;
+
+/// Error from reading the input or checking the pieces
+pub enum Error {
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// A file doesn't have the expected format
+    Parse(String),
+    /// More than `MAX_PIECES` pieces
+    PieceCount(usize),
+    /// Piece with this id can't be used
+    InvalidPiece(usize, PieceError),
+    /// The pieces don't have as many cells as the cube, with the cell count of each piece
+    Volume(String),
+    /// A fixed placement can't be used, see `fix_placements`
+    InvalidFix(String),
+    /// The search found no solution where one was needed
+    NoSolution,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Parse(msg) => write!(f, "{}", msg),
+            Error::PieceCount(count) => {
+                write!(f, "Expected at most {} pieces, got {}", MAX_PIECES, count)
+            }
+            Error::InvalidPiece(piece, err) => write!(f, "Invalid piece {}: {}", piece, err),
+            Error::Volume(msg) => write!(f, "{}", msg),
+            Error::InvalidFix(msg) => write!(f, "Invalid fixed placement: {}", msg),
+            Error::NoSolution => write!(f, "Found no solution"),
+        }
+    }
+}
+
+// Same as Display, so that returning the error from main prints a readable message
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Check that there are at most `MAX_PIECES` pieces, that every piece is valid and
+/// that they fill the cube
+pub fn validate_pieces(puzzle: &Puzzle, pieces: &[Piece]) -> Result<(), Error> {
+    if pieces.len() > MAX_PIECES {
+        return Err(Error::PieceCount(pieces.len()));
+    }
+    for piece in pieces {
+        validate_piece(puzzle, piece.bits).map_err(|err| Error::InvalidPiece(piece.id, err))?;
+    }
+    let bits: Vec<_> = pieces.iter().map(|piece| piece.bits).collect();
+    check_volume(puzzle, &bits).map_err(Error::Volume)
+}
+
+/// Check that the pieces together have exactly as many cells as the cube
+pub fn check_volume(puzzle: &Puzzle, pieces: &[BitCube]) -> Result<(), String> {
+    let total: usize = pieces.iter().map(|piece| piece.count_ones() as usize).sum();
+    if total == puzzle.num_bits() {
+        return Ok(());
+    }
+    let mut message = format!(
+        "Pieces have {} cells in total, expected {} to fill the cube",
+        total,
+        puzzle.num_bits()
+    );
+    for (piece, bits) in pieces.iter().enumerate() {
+        message += &format!("\n  Piece {}: {} cells", piece, bits.count_ones());
+    }
+    Err(message)
+}
+
+/// Whether every piece is placed in the cube without overlapping another, which fills
+/// the cube exactly when the pieces have as many cells as the cube
+///
+/// The placements don't overlap if their union has as many cells as all of them together.
+pub fn validate_solution(puzzle: &Puzzle, solution: &Solution) -> bool {
+    let union = solution
+        .0
+        .iter()
+        .fold(BitCube::default(), |union, placement| union | *placement);
+    let total: u32 = solution
+        .0
+        .iter()
+        .map(|placement| placement.count_ones())
+        .sum();
+    solution.0.len() == puzzle.num_pieces
+        && solution.0.iter().all(|placement| !placement.is_empty())
+        && union.count_ones() == total
+        && union & puzzle.mask() == union
+}
+
+/// Problems that keep the solution from being a solution of the pieces, empty if there
+/// are none, e.g. for a solution read back with `parse_solution`
+///
+/// Every piece has to be placed, the cells of each piece have to be a placement of it,
+/// a rotation and translation or only a translation for fixed pieces, and the cells have
+/// to be filled as far as the pieces can fill them. Finally `validate_solution` has to
+/// accept it.
+pub fn verify_solution(puzzle: &Puzzle, pieces: &[Piece], solution: &Solution) -> Vec<String> {
+    let mut problems = Vec::new();
+    if solution.0.len() != pieces.len() {
+        problems.push(format!(
+            "{} pieces in the solution, but {} in the piece set",
+            solution.0.len(),
+            pieces.len()
+        ));
+        return problems;
+    }
+    for (piece, placement) in pieces.iter().zip(&solution.0) {
+        if placement.is_empty() {
+            problems.push(format!("Piece {} isn't placed", piece.label()));
+            continue;
+        }
+        let matches = if piece.fixed {
+            normalize(puzzle, *placement) == normalize(puzzle, piece.bits)
+        } else {
+            canonical_piece(puzzle, *placement) == canonical_piece(puzzle, piece.bits)
+        };
+        if !matches {
+            problems.push(format!(
+                "The {} cells of piece {} aren't a placement of it",
+                placement.count_ones(),
+                piece.label()
+            ));
+        }
+    }
+    let filled: u32 = solution
+        .0
+        .iter()
+        .map(|placement| placement.count_ones())
+        .sum();
+    let volume: u32 = pieces.iter().map(|piece| piece.bits.count_ones()).sum();
+    let cells = puzzle.num_bits() as u32;
+    if filled < volume.min(cells) {
+        problems.push(format!(
+            "{} cells are empty that the pieces should fill",
+            volume.min(cells) - filled
+        ));
+    }
+    if problems.is_empty() && !validate_solution(puzzle, solution) {
+        problems.push("The pieces overlap or leave the box".to_string());
+    }
+    problems
+}
+
+/// Find groups of pieces with the same shape
+pub fn congruent_pieces(puzzle: &Puzzle, pieces: &[BitCube]) -> Vec<Vec<usize>> {
+    let mut groups = std::collections::BTreeMap::<BitCube, Vec<usize>>::new();
+    for (piece, bits) in pieces.iter().enumerate() {
+        groups
+            .entry(canonical_piece(puzzle, *bits))
+            .or_default()
+            .push(piece);
+    }
+    let mut groups: Vec<_> = groups
+        .into_values()
+        .filter(|pieces| pieces.len() > 1)
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Map every piece to the piece with the shape of its mirror image
+///
+/// Pieces that are their own mirror image map to themselves. Returns None if the mirror
+/// image of some piece isn't in the set, then no mirrored solution can be a solution.
+pub fn mirror_pieces(puzzle: &Puzzle, pieces: &[BitCube]) -> Option<Vec<usize>> {
+    let shapes: Vec<_> = pieces
+        .iter()
+        .map(|piece| canonical_piece(puzzle, *piece))
+        .collect();
+    let mut mirror = vec![usize::MAX; pieces.len()];
+    for (piece, bits) in pieces.iter().enumerate() {
+        let shape = canonical_piece(puzzle, mirror_piece(puzzle, *bits));
+        // Pair up pieces with the same shape in order, so that the map is a permutation
+        let other = (0..pieces.len())
+            .find(|other| shapes[*other] == shape && !mirror[..piece].contains(other))?;
+        mirror[piece] = other;
+    }
+    Some(mirror)
+}
+
+/// Distinct orientations of a piece that fit the box, each moved to the origin and sorted
+///
+/// 24 for a piece without rotational symmetry in a cube, fewer for symmetric pieces and
+/// for orientations that stick out of a box that isn't a cube.
+pub fn orientations(puzzle: &Puzzle, piece: BitCube) -> Vec<BitCube> {
+    let mut orientations: Vec<_> = (0..24)
+        .map(|nr| orient_normalized(puzzle, piece, nr))
+        .filter(|oriented| oriented.count_ones() == piece.count_ones())
+        .collect();
+    orientations.sort();
+    orientations.dedup();
+    orientations
+}
+
+/// Generate all unique placements (with all possible rotations and translation) of a piece
+///
+/// A `fixed` piece is only translated, keeping the orientation it is given in. The
+/// placements are sorted by bitmask, which fixes the order of the search and of the
+/// solutions it finds.
+///
+/// Mirror images of the piece are only placed if `with_mirrors` is set, for puzzle
+/// variants that allow them, and never for a fixed piece. A solid piece can't be turned
+/// into its mirror image, so the search passes false even when a solution and its mirror
+/// image count as the same (see `mirror_pieces`): that only affects which solutions are
+/// unique, the mirror image of a solution uses the mirrored pieces of the set.
+pub fn generate_placements(
+    puzzle: &Puzzle,
+    piece: BitCube,
+    fixed: bool,
+    with_mirrors: bool,
+) -> Vec<BitCube> {
+    // number of bits in a piece, should always be the same
+    // if not, the orientation doesn't fit the box or has been shifted outside it
+    let num_bits = piece.count_ones();
+
+    let [size_x, size_y, size_z] = puzzle.dims.map(|d| d as i32);
+    let mut oriented = if fixed {
+        vec![normalize(puzzle, piece)]
+    } else {
+        orientations(puzzle, piece)
+    };
+    if with_mirrors && !fixed {
+        oriented.extend(orientations(puzzle, mirror_piece(puzzle, piece)));
+    }
+    let mut set = std::collections::HashSet::new();
+    for piece in oriented {
+        for z in 0..size_z {
+            for y in 0..size_y {
+                for x in 0..size_x {
+                    let piece2 = translate(puzzle, piece, x, y, z);
+                    if piece2.count_ones() == num_bits {
+                        set.insert(piece2);
+                    }
+                }
+            }
+        }
+    }
+
+    // Sorted so that the search order is the same in every run
+    let mut placements: Vec<BitCube> = set.into_iter().collect();
+    placements.sort();
+    // Every placement has to keep all bits of the piece and stay within the cube,
+    // otherwise the rotate/translate pipeline has leaked bits outside the cube.
+    debug_assert!(placements
+        .iter()
+        .all(|p| p.count_ones() == num_bits && *p & puzzle.mask() == *p));
+    placements
+}
+
+/// Generate the placements of every piece, see `generate_placements`, without mirror images
+///
+/// `fixed` flags the pieces that are only translated, it can be shorter than `pieces` if
+/// the rest aren't fixed. Each piece is handled on its own thread, the placements are in
+/// the same order as the pieces no matter which thread finishes first.
+pub fn generate_all_placements(
+    puzzle: &Puzzle,
+    pieces: &[BitCube],
+    fixed: &[bool],
+) -> Vec<Vec<BitCube>> {
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                let fixed = fixed.get(i).copied().unwrap_or(false);
+                scope.spawn(move || generate_placements(puzzle, *piece, fixed, false))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Placements of every piece, in the order of the pieces, see `Piece::placements`
+///
+/// Unlike the search, which limits the placements of a piece to break the symmetries of
+/// the box, these are all placements, for building other solvers or tools on top.
+pub fn all_placements(puzzle: &Puzzle, pieces: &[Piece]) -> Vec<Vec<BitCube>> {
+    let bits: Vec<_> = pieces.iter().map(|piece| piece.bits).collect();
+    let fixed: Vec<_> = pieces.iter().map(|piece| piece.fixed).collect();
+    generate_all_placements(puzzle, &bits, &fixed)
+}
+
+/// Depth down to which the search tree is weighted for the progress estimate
+const PROGRESS_DEPTH: usize = 3;
+
+pub struct Stats {
+    pub num_permutations: usize,
+    pub num_solutions: usize,
+    /// Canonical forms of the solutions found so far, if unique solutions are counted during the search
+    pub unique_solutions: Option<std::collections::HashSet<[u8; MAX_CELLS]>>,
+    /// Piece map for reflections, if mirrored solutions count as the same, see `canonical_key`
+    pub mirror: Option<Vec<usize>>,
+    /// Number of nodes visited and viable choices found at each depth, if profiled
+    pub branching: Option<Vec<(usize, usize)>>,
+    /// Number of placements tried and of those that led to no solution for each piece, if profiled
+    pub piece_stats: Option<Vec<(usize, usize)>>,
+    /// Seconds into the search at which each solution was found, if recorded
+    pub solution_times: Option<Vec<f64>>,
+    /// Seconds into the search at which the first solution was found
+    pub first_solution_time: Option<f64>,
+    /// Don't print progress, used for the stats of each search thread
+    pub quiet: bool,
+    /// Estimated fraction of the search tree that has been completed, 0 to 1
+    ///
+    /// Every node down to `PROGRESS_DEPTH` splits its weight evenly between its children,
+    /// the weight is added to the progress as the subtrees at that depth are completed.
+    pub progress: f64,
+    /// Weight of the current node at each depth down to `PROGRESS_DEPTH`
+    progress_weights: [f64; PROGRESS_DEPTH + 1],
+    /// Stop the search to write a checkpoint this often
+    pub checkpoint_interval: Option<std::time::Duration>,
+    last_checkpoint: std::time::Instant,
+    /// Picks of the node the search stopped at for a checkpoint
+    pub checkpoint: Option<Solution>,
+    /// Picks of the node to resume the search at, the search skips everything before it
+    pub resume: Option<Solution>,
+    /// Stop the search once this point in time has passed
+    pub deadline: Option<std::time::Instant>,
+    /// Whether the search was stopped because the deadline passed
+    pub timed_out: bool,
+
+    start: std::time::Instant,
+    /// Progress when the search started, for resumed searches
+    start_progress: f64,
+    last_print: std::time::Instant,
+    last_print_permutations: usize,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            num_permutations: 0,
+            num_solutions: 0,
+            unique_solutions: None,
+            mirror: None,
+            branching: None,
+            piece_stats: None,
+            solution_times: None,
+            first_solution_time: None,
+            quiet: false,
+            progress: 0.0,
+            progress_weights: [1.0; PROGRESS_DEPTH + 1],
+            checkpoint_interval: None,
+            last_checkpoint: std::time::Instant::now(),
+            checkpoint: None,
+            resume: None,
+            deadline: None,
+            timed_out: false,
+            start: std::time::Instant::now(),
+            start_progress: 0.0,
+            last_print: std::time::Instant::now(),
+            last_print_permutations: 0,
+        }
+    }
+    /// Empty, quiet stats tracking the same things as these, for a search thread
+    ///
+    /// `weight` is the share of the whole search that the thread will cover.
+    pub fn for_thread(&self, weight: f64) -> Self {
+        let mut stats = Self::new();
+        stats.unique_solutions = self.unique_solutions.as_ref().map(|_| Default::default());
+        stats.mirror = self.mirror.clone();
+        stats.branching = self
+            .branching
+            .as_ref()
+            .map(|branching| vec![(0, 0); branching.len()]);
+        stats.piece_stats = self
+            .piece_stats
+            .as_ref()
+            .map(|piece_stats| vec![(0, 0); piece_stats.len()]);
+        stats.solution_times = self.solution_times.as_ref().map(|_| Vec::new());
+        stats.quiet = true;
+        stats.start = self.start;
+        stats.deadline = self.deadline;
+        stats.progress_weights[0] = weight;
+        stats
+    }
+    /// Add the stats of a search thread to these
+    pub fn add(&mut self, other: &Stats) {
+        self.num_permutations += other.num_permutations;
+        self.num_solutions += other.num_solutions;
+        self.progress += other.progress;
+        self.timed_out |= other.timed_out;
+        if let Some(time) = other.first_solution_time {
+            self.first_solution_time = Some(
+                self.first_solution_time
+                    .map_or(time, |first| first.min(time)),
+            );
+        }
+        if let (Some(unique_solutions), Some(other)) =
+            (&mut self.unique_solutions, &other.unique_solutions)
+        {
+            unique_solutions.extend(other.iter().cloned());
+        }
+        if let (Some(branching), Some(other)) = (&mut self.branching, &other.branching) {
+            for (depth, (nodes, choices)) in other.iter().enumerate() {
+                branching[depth].0 += nodes;
+                branching[depth].1 += choices;
+            }
+        }
+        if let (Some(piece_stats), Some(other)) = (&mut self.piece_stats, &other.piece_stats) {
+            for (piece, (tried, dead_ends)) in other.iter().enumerate() {
+                piece_stats[piece].0 += tried;
+                piece_stats[piece].1 += dead_ends;
+            }
+        }
+        if let (Some(solution_times), Some(other)) =
+            (&mut self.solution_times, &other.solution_times)
+        {
+            solution_times.extend(other);
+        }
+    }
+    /// Print the progress to stderr, at most once a second and not at all if `quiet`
+    pub fn print(&mut self) {
+        if self.quiet {
+            return;
+        }
+        let now = std::time::Instant::now();
+        let elapsed = (now - self.last_print).as_secs_f64();
+        if elapsed < 1.0 {
+            return;
+        }
+
+        let permutations = self.num_permutations - self.last_print_permutations;
+        eprint!(
+            "Permutations: {}, Solutions: {}, ",
+            self.num_permutations, self.num_solutions,
+        );
+        if let Some(unique_solutions) = &self.unique_solutions {
+            eprint!("Unique solutions: {}, ", unique_solutions.len());
+        }
+        eprint!("Permutations/s: {}", permutations as f64 / elapsed);
+        let progress = self.progress.min(1.0);
+        if progress > self.start_progress {
+            let elapsed = (now - self.start).as_secs_f64();
+            let rate = (progress - self.start_progress) / elapsed;
+            eprint!(
+                ", Progress: {:.2}%, ETA: {}",
+                progress * 100.0,
+                format_duration((1.0 - progress) / rate)
+            );
+        }
+        eprintln!();
+        self.last_print = now;
+        self.last_print_permutations = self.num_permutations;
+    }
+    pub fn success(&mut self, puzzle: &Puzzle, solution: &Solution) {
+        self.num_solutions += 1;
+        self.num_permutations += 1;
+        if self.first_solution_time.is_none() {
+            self.first_solution_time = Some(self.start.elapsed().as_secs_f64());
+        }
+        if let Some(unique_solutions) = &mut self.unique_solutions {
+            unique_solutions.insert(canonical_key(puzzle, solution, self.mirror.is_some()));
+        }
+        if let Some(solution_times) = &mut self.solution_times {
+            solution_times.push(self.start.elapsed().as_secs_f64());
+        }
+    }
+    pub fn fail(&mut self) {
+        self.num_permutations += 1;
+    }
+    /// Continue from a checkpoint, the next search resumes at the node it was written at
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        self.num_permutations = checkpoint.num_permutations;
+        self.last_print_permutations = checkpoint.num_permutations;
+        self.num_solutions = checkpoint.num_solutions;
+        self.progress = checkpoint.progress;
+        self.start_progress = checkpoint.progress;
+        self.resume = Some(checkpoint.picks.clone());
+    }
+    /// Whether it is time to stop the search and write a checkpoint
+    fn checkpoint_due(&self) -> bool {
+        self.checkpoint_interval
+            .is_some_and(|interval| self.last_checkpoint.elapsed() >= interval)
+    }
+    /// Whether the search should stop because the deadline has passed
+    ///
+    /// The clock is only read every 1024 permutations, as reading it at every node would
+    /// slow down the search.
+    pub fn time_is_up(&mut self) -> bool {
+        if self.num_permutations.is_multiple_of(1024)
+            && self
+                .deadline
+                .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+        {
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+    /// Stop the search at the node with these picks to write a checkpoint
+    fn stop_for_checkpoint(&mut self, picks: &Solution) {
+        self.checkpoint = Some(picks.clone());
+        self.resume = Some(picks.clone());
+        self.last_checkpoint = std::time::Instant::now();
+    }
+    /// Split the weight of a node at `depth` between its `choices` children
+    fn split_progress(&mut self, depth: usize, choices: usize) {
+        if depth < PROGRESS_DEPTH && choices > 0 {
+            self.progress_weights[depth + 1] = self.progress_weights[depth] / choices as f64;
+        }
+    }
+    /// Mark the node at `depth` as completed, `choices` is the number of children it had
+    fn complete(&mut self, depth: usize, choices: usize) {
+        // Nodes above PROGRESS_DEPTH with children have handed their weight to them
+        if depth == PROGRESS_DEPTH || (depth < PROGRESS_DEPTH && choices == 0) {
+            self.progress += self.progress_weights[depth];
+        }
+    }
+    /// Record the number of viable choices at a node at the given depth
+    pub fn branch(&mut self, depth: usize, choices: usize) {
+        if let Some(branching) = &mut self.branching {
+            branching[depth].0 += 1;
+            branching[depth].1 += choices;
+        }
+    }
+    pub fn print_branching(&self) {
+        let Some(branching) = &self.branching else {
+            return;
+        };
+        println!("Depth         Nodes  Branching factor");
+        for (depth, (nodes, choices)) in branching.iter().enumerate() {
+            if *nodes == 0 {
+                continue;
+            }
+            println!(
+                "{:>5}  {:>12}  {:.3}",
+                depth,
+                nodes,
+                *choices as f64 / *nodes as f64
+            );
+        }
+        println!();
+    }
+    /// Record that a placement of the piece was searched, `dead_end` if it led to no solution
+    pub fn placed(&mut self, piece: usize, dead_end: bool) {
+        if let Some(piece_stats) = &mut self.piece_stats {
+            piece_stats[piece].0 += 1;
+            piece_stats[piece].1 += dead_end as usize;
+        }
+    }
+    pub fn print_piece_stats(&self) {
+        let Some(piece_stats) = &self.piece_stats else {
+            return;
+        };
+        println!("Piece         Tried     Dead ends  Dead end %");
+        for (piece, (tried, dead_ends)) in piece_stats.iter().enumerate() {
+            println!(
+                "{:>5}  {:>12}  {:>12}  {:>9.2}%",
+                piece,
+                tried,
+                dead_ends,
+                100.0 * *dead_ends as f64 / (*tried).max(1) as f64
+            );
+        }
+        println!();
+    }
+    /// Print how many solutions were found in each second of the search, or in longer
+    /// intervals for long searches so that the histogram stays at most `HISTOGRAM_ROWS` rows
+    pub fn print_histogram(&self) {
+        let Some(solution_times) = &self.solution_times else {
+            return;
+        };
+        let end = self.start.elapsed().as_secs_f64();
+        let interval = (end / HISTOGRAM_ROWS as f64).ceil().max(1.0);
+        let mut counts = vec![0; (end / interval) as usize + 1];
+        for time in solution_times {
+            let bucket = ((time / interval) as usize).min(counts.len() - 1);
+            counts[bucket] += 1;
+        }
+        let max = counts.iter().copied().max().unwrap_or(0).max(1);
+        println!("Seconds        Solutions");
+        for (i, count) in counts.iter().enumerate() {
+            println!(
+                "{:>7}  {:>12}  {}",
+                i * interval as usize,
+                count,
+                "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max))
+            );
+        }
+        println!();
+    }
+}
+
+/// Maximum number of rows of the solution histogram
+const HISTOGRAM_ROWS: usize = 30;
+/// Width of the longest bar of the solution histogram
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Format a number of seconds as hours, minutes and seconds
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Restrict one piece to a single placement out of every group of placements that are
+/// rotations of each other within the box, so that every solution is found in only one of
+/// its rotations, all 24 in a cube or the ones in `Puzzle::symmetries` for other boxes
+///
+/// Only pieces without rotational symmetry qualify, as a rotation mapping the piece onto
+/// itself would otherwise lose the solutions using the skipped placements. The qualifying
+/// piece with the fewest placements is restricted and returned, None if there is none.
+pub fn break_symmetry(puzzle: &Puzzle, piece_placements: &mut [Vec<BitCube>]) -> Option<usize> {
+    let symmetries = puzzle.symmetries();
+    let piece = (0..piece_placements.len())
+        .filter(|piece| {
+            let Some(placement) = piece_placements[*piece].first() else {
+                return false;
+            };
+            let orientations: std::collections::HashSet<_> = symmetries
+                .iter()
+                .map(|nr| normalize(puzzle, orient_piece(puzzle, *placement, *nr)))
+                .collect();
+            orientations.len() == symmetries.len()
+        })
+        .min_by_key(|piece| piece_placements[*piece].len())?;
+
+    piece_placements[piece].retain(|placement| {
+        symmetries
+            .iter()
+            .all(|nr| orient_piece(puzzle, *placement, *nr) >= *placement)
+    });
+    Some(piece)
+}
+
+/// Restrict pieces to a single, fixed placement each, so that the search only finds
+/// solutions with the pieces in those places
+///
+/// `fixed` are (piece, placement) pairs. Every placement has to be one of the placements
+/// of its piece, no piece can be fixed twice and the fixed placements can't overlap.
+pub fn fix_placements(
+    puzzle: &Puzzle,
+    piece_placements: &mut [Vec<BitCube>],
+    fixed: &[(usize, BitCube)],
+) -> Result<(), Error> {
+    let mut state = BitCube::default();
+    for (i, &(piece, placement)) in fixed.iter().enumerate() {
+        if piece >= piece_placements.len() {
+            return Err(Error::InvalidFix(format!("there is no piece {}", piece)));
+        }
+        if fixed[..i].iter().any(|(other, _)| *other == piece) {
+            return Err(Error::InvalidFix(format!("piece {} is fixed twice", piece)));
+        }
+        if placement & puzzle.mask() != placement {
+            return Err(Error::InvalidFix(format!(
+                "{:x} for piece {} is outside the cube",
+                placement, piece
+            )));
+        }
+        if piece_placements[piece].binary_search(&placement).is_err() {
+            return Err(Error::InvalidFix(format!(
+                "{:x} is not a placement of piece {}",
+                placement, piece
+            )));
+        }
+        if !state.is_disjoint(placement) {
+            return Err(Error::InvalidFix(format!(
+                "{:x} for piece {} overlaps another fixed piece",
+                placement, piece
+            )));
+        }
+        state |= placement;
+        piece_placements[piece] = vec![placement];
+    }
+    Ok(())
+}
+
+/// Find placements that are shared by more than one piece
+/// Returns (placement, pieces) pairs, sorted by placement
+pub fn shared_placements(piece_placements: &[Vec<BitCube>]) -> Vec<(BitCube, Vec<usize>)> {
+    let mut owners = std::collections::HashMap::<BitCube, Vec<usize>>::new();
+    for (piece, placements) in piece_placements.iter().enumerate() {
+        for placement in placements {
+            owners.entry(*placement).or_default().push(piece);
+        }
+    }
+    let mut shared: Vec<_> = owners
+        .into_iter()
+        .filter(|(_, pieces)| pieces.len() > 1)
+        .collect();
+    shared.sort();
+    shared
+}
+
+/// Lazily iterate the placements that cover the given cell
+pub fn placements_for_cell(
+    placements: &[BitCube],
+    cell: usize,
+) -> impl Iterator<Item = BitCube> + '_ {
+    placements.iter().copied().filter(move |p| p.test_bit(cell))
+}
+
+/// Placements of all pieces, indexed for the search
+pub struct Placements {
+    pub puzzle: Puzzle,
+    /// Number of cells in each piece
+    pub piece_sizes: Vec<u32>,
+    /// All placements of each piece
+    pub piece_placements: Vec<Vec<BitCube>>,
+    /// For every bit in the cube, map it to each piece and the placements of that piece covering the bit
+    /// bit_map[bit_index][piece] = [placement0, placement1, ...]
+    /// None if placements are filtered on demand instead, trading speed for memory
+    bit_map: Option<Vec<Vec<Vec<BitCube>>>>,
+    /// Number of cells left empty in a solution, when the pieces have fewer cells than the box
+    pub num_holes: usize,
+}
+
+impl Placements {
+    pub fn new(puzzle: Puzzle, piece_placements: Vec<Vec<BitCube>>, with_bit_map: bool) -> Self {
+        let piece_sizes: Vec<u32> = piece_placements
+            .iter()
+            .map(|placements| placements.first().map_or(0, |p| p.count_ones()))
+            .collect();
+
+        let bit_map = with_bit_map.then(|| {
+            (0..puzzle.num_bits())
+                .map(|bi| {
+                    piece_placements
+                        .iter()
+                        .map(|placements| placements_for_cell(placements, bi).collect())
+                        .collect()
+                })
+                .collect()
+        });
+        let num_holes = puzzle
+            .num_bits()
+            .saturating_sub(piece_sizes.iter().sum::<u32>() as usize);
+        Self {
+            puzzle,
+            piece_sizes,
+            piece_placements,
+            bit_map,
+            num_holes,
+        }
+    }
+    /// Number of cells of `state` that no piece in `used_pieces` fills, which the search
+    /// has left empty
+    pub fn holes(&self, state: BitCube, used_pieces: u64) -> usize {
+        if self.num_holes == 0 {
+            return 0;
+        }
+        let filled: u32 = (0..self.piece_sizes.len())
+            .filter(|piece| used_pieces & (1 << piece) != 0)
+            .map(|piece| self.piece_sizes[piece])
+            .sum();
+        (state.count_ones() - filled) as usize
+    }
+
+    /// Bytes used by the placements of the pieces and by the bit map, if there is one
+    ///
+    /// Counts the elements of the vectors, not their spare capacity or headers.
+    pub fn memory_usage(&self) -> usize {
+        let placements: usize = self.piece_placements.iter().map(Vec::len).sum();
+        let bit_map: usize = self.bit_map.as_ref().map_or(0, |bit_map| {
+            bit_map
+                .iter()
+                .flat_map(|pieces| pieces.iter().map(Vec::len))
+                .sum()
+        });
+        (placements + bit_map) * std::mem::size_of::<BitCube>()
+    }
+
+    /// Placements of `piece` covering `cell`
+    pub fn for_cell(&self, cell: usize, piece: usize) -> impl Iterator<Item = BitCube> + '_ {
+        placements_for_cell(self.candidates(cell, piece), cell)
+    }
+    /// Placements of the pieces not in `used_pieces` that cover `cell` and only cells that
+    /// are empty in `state`, each with its piece
+    ///
+    /// These are the choices of the search at a node that branches on `cell`, in the order
+    /// they are tried unless the order is randomized.
+    pub fn fitting(
+        &self,
+        cell: usize,
+        state: BitCube,
+        used_pieces: u64,
+    ) -> impl Iterator<Item = (usize, BitCube)> + '_ {
+        (0..self.piece_placements.len())
+            .filter(move |piece| used_pieces & (1 << piece) == 0)
+            .flat_map(move |piece| {
+                self.for_cell(cell, piece)
+                    .filter(move |p| p.is_disjoint(state))
+                    .map(move |p| (piece, p))
+            })
+    }
+    /// Placements of `piece` that `for_cell` picks the ones covering `cell` from
+    fn candidates(&self, cell: usize, piece: usize) -> &[BitCube] {
+        match &self.bit_map {
+            Some(bit_map) => &bit_map[cell][piece],
+            None => &self.piece_placements[piece],
+        }
+    }
+}
+
+/// Whether the pieces can cover the cells of each color of the box in a coloring by
+/// (x + y + z) mod `num_colors`, a cheap check that rules out some piece sets before searching
+///
+/// Every placement of a piece covers some number of cells of each color, and a solution
+/// picks one placement per piece so that the counts add up to the counts of the box (at
+/// most those, when the pieces have fewer cells than the box). Returns false only if no
+/// choice of placements adds up, true doesn't mean that a solution exists. With two colors
+/// this is the checkerboard argument, e.g. the T tetromino always covers 3 cells of one
+/// color and 1 of the other.
+pub fn coloring_feasible(
+    puzzle: &Puzzle,
+    piece_placements: &[Vec<BitCube>],
+    num_colors: usize,
+) -> bool {
+    let mut colors = vec![BitCube::default(); num_colors];
+    for x in 0..puzzle.dims[0] {
+        for y in 0..puzzle.dims[1] {
+            for z in 0..puzzle.dims[2] {
+                colors[(x + y + z) % num_colors] |= pack_bit(puzzle, true, x, y, z);
+            }
+        }
+    }
+    let counts = |block: BitCube| -> Vec<u32> {
+        colors
+            .iter()
+            .map(|color| (block & *color).count_ones())
+            .collect()
+    };
+    let target = counts(puzzle.mask());
+
+    // Color counts reachable with the pieces so far
+    let mut reachable = std::collections::HashSet::from([vec![0; num_colors]]);
+    for placements in piece_placements {
+        let piece_counts: std::collections::HashSet<_> = placements
+            .iter()
+            .map(|placement| counts(*placement))
+            .collect();
+        reachable = reachable
+            .iter()
+            .flat_map(|sum| {
+                piece_counts.iter().map(move |counts| {
+                    sum.iter()
+                        .zip(counts)
+                        .map(|(a, b)| a + b)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .filter(|sum| sum.iter().zip(&target).all(|(a, b)| a <= b))
+            .collect();
+    }
+    let total: u32 = target.iter().sum();
+    let volume: u32 = piece_placements
+        .iter()
+        .map(|placements| placements.first().map_or(0, |p| p.count_ones()))
+        .sum();
+    // Pieces with fewer cells than the box only need to fit within the counts of each color
+    (volume < total && !reachable.is_empty()) || reachable.contains(&target)
+}
+
+/// Split the set bits of a block into face-connected components
+pub fn connected_components(puzzle: &Puzzle, block: BitCube) -> Vec<BitCube> {
+    let mut components = Vec::new();
+    let mut rest = block;
+    while !rest.is_empty() {
+        // Flood fill from the lowest remaining bit
+        let mut component = rest.lowest_bit();
+        loop {
+            let next = puzzle.grow(component) & rest;
+            if next == component {
+                break;
+            }
+            component = next;
+        }
+        components.push(component);
+        rest &= !component;
+    }
+    components
+}
+
+/// Cheap check whether a partial state can still be completed with the unused pieces
+///
+/// Checks that the empty volume matches the unused pieces, that every empty cell can be
+/// covered by some unused piece, and that every connected empty region can be filled by
+/// some subset of the unused pieces. Returns false only if the state is definitely
+/// unsolvable, true does not guarantee that a solution exists.
+pub fn quick_feasible(state: BitCube, used_pieces: u64, placements: &Placements) -> bool {
+    let empty = !state & placements.puzzle.mask();
+
+    let (sums, volume) = unused_size_sums(used_pieces, placements);
+    if volume != empty.count_ones() {
+        return false;
+    }
+
+    let mut cells = empty;
+    while !cells.is_empty() {
+        let bit_index = cells.trailing_zeros() as usize;
+        cells &= !cells.lowest_bit();
+        let coverable = (0..placements.piece_sizes.len())
+            .filter(|piece| used_pieces & (1 << piece) == 0)
+            .any(|piece| {
+                placements
+                    .for_cell(bit_index, piece)
+                    .any(|p| p.is_disjoint(state))
+            });
+        if !coverable {
+            return false;
+        }
+    }
+
+    regions_fit(&placements.puzzle, empty, sums)
+}
+
+/// Check that every connected empty region can be filled by some subset of the unused pieces
+///
+/// The cheapest part of `quick_feasible`, it rules out states where a pocket of empty
+/// cells is smaller than the smallest unused piece or doesn't add up to any of their sizes.
+pub fn regions_feasible(state: BitCube, used_pieces: u64, placements: &Placements) -> bool {
+    let empty = !state & placements.puzzle.mask();
+    let (sums, _) = unused_size_sums(used_pieces, placements);
+    regions_fit(&placements.puzzle, empty, sums)
+}
+
+/// Region sizes reachable as sums of unused piece sizes, bit n set if n is reachable,
+/// and the total size of the unused pieces
+///
+/// Only sums up to 127 fit, which leaves out a region of all 128 cells of the largest
+/// boxes, see `regions_fit`.
+fn unused_size_sums(used_pieces: u64, placements: &Placements) -> (u128, u32) {
+    let mut sums: u128 = 1;
+    let mut volume = 0;
+    for (piece, size) in placements.piece_sizes.iter().enumerate() {
+        if used_pieces & (1 << piece) == 0 {
+            sums |= sums.checked_shl(*size).unwrap_or(0);
+            volume += size;
+        }
+    }
+    (sums, volume)
+}
+
+/// Whether the size of every connected region of `empty` is one of the `sums`
+///
+/// A region of 128 cells is past the sums and always fits.
+fn regions_fit(puzzle: &Puzzle, empty: BitCube, sums: u128) -> bool {
+    // Same flood fill as connected_components, stopping at the first region that doesn't fit
+    let mut rest = empty;
+    while !rest.is_empty() {
+        let mut region = rest.lowest_bit();
+        loop {
+            let next = puzzle.grow(region) & rest;
+            if next == region {
+                break;
+            }
+            region = next;
+        }
+        if sums
+            .checked_shr(region.count_ones())
+            .is_some_and(|sums| sums & 1 == 0)
+        {
+            return false;
+        }
+        rest &= !region;
+    }
+    true
+}
+
+/// Number of placements of the unused pieces that fit in each cell, kept up to date as the
+/// search places pieces and backtracks, see `SearchOptions::forward_checking`
+///
+/// Placing a piece removes its own placements and the placements of the other pieces that
+/// overlap it, leaving a cell empty removes the placements covering the cell. The cells of
+/// every removed placement are counted down, and up again when the search backtracks.
+struct CandidateCounts {
+    counts: Vec<u32>,
+    /// Cells that no placement fits in, filled or not
+    uncovered: BitCube,
+}
+
+impl CandidateCounts {
+    fn new(state: BitCube, used_pieces: u64, placements: &Placements) -> Self {
+        let mut candidates = Self {
+            counts: vec![0; placements.puzzle.num_bits()],
+            uncovered: placements.puzzle.mask(),
+        };
+        for (piece, piece_placements) in placements.piece_placements.iter().enumerate() {
+            if used_pieces & (1 << piece) == 0 {
+                for placement in piece_placements.iter().filter(|p| p.is_disjoint(state)) {
+                    candidates.count(*placement, false);
+                }
+            }
+        }
+        candidates
+    }
+    /// Remove the placements that no longer fit once `mask` is filled in `state`, by `piece`
+    /// or left empty if None, or put them back if `remove` isn't set
+    fn update(
+        &mut self,
+        state: BitCube,
+        used_pieces: u64,
+        piece: Option<usize>,
+        mask: BitCube,
+        placements: &Placements,
+        remove: bool,
+    ) {
+        for other in 0..placements.piece_sizes.len() {
+            if used_pieces & (1 << other) != 0 {
+                continue;
+            }
+            if piece == Some(other) {
+                for placement in placements.piece_placements[other]
+                    .iter()
+                    .filter(|p| p.is_disjoint(state))
+                {
+                    self.count(*placement, remove);
+                }
+                continue;
+            }
+            let mut cells = mask;
+            while !cells.is_empty() {
+                let cell = cells.trailing_zeros() as usize;
+                cells &= !cells.lowest_bit();
+                for placement in placements.for_cell(cell, other) {
+                    // A placement overlapping several cells of the mask is counted at the lowest
+                    if placement.is_disjoint(state)
+                        && (placement & mask).trailing_zeros() as usize == cell
+                    {
+                        self.count(placement, remove);
+                    }
+                }
+            }
+        }
+    }
+    /// Count the cells of the placement down if `remove` is set, otherwise up
+    fn count(&mut self, placement: BitCube, remove: bool) {
+        let mut cells = placement;
+        while !cells.is_empty() {
+            let cell = cells.trailing_zeros() as usize;
+            cells &= !cells.lowest_bit();
+            if remove {
+                self.counts[cell] -= 1;
+                if self.counts[cell] == 0 {
+                    self.uncovered.set_bit(cell);
+                }
+            } else {
+                self.counts[cell] += 1;
+                self.uncovered &= !BitCube(1 << cell);
+            }
+        }
+    }
+    /// Whether the empty cells that no placement fits in can all be left empty
+    fn feasible(&self, state: BitCube, holes_left: usize) -> bool {
+        (self.uncovered & !state).count_ones() as usize <= holes_left
+    }
+}
+
+/// How the search picks the empty cell to branch on
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum CellHeuristic {
+    /// The lowest empty bit, the first empty cell along z, then y, then x
+    LowestBit,
+    /// The empty cell covered by the fewest placements that fit, ties broken by lowest bit
+    #[default]
+    MostConstrained,
+    /// The empty cell closest to a corner of the box, ties broken by lowest bit
+    CornerFirst,
+}
+
+impl std::str::FromStr for CellHeuristic {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "lowest-bit" => Ok(Self::LowestBit),
+            "most-constrained" => Ok(Self::MostConstrained),
+            "corner-first" => Ok(Self::CornerFirst),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Order the search tries the pieces in at each node
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum PieceOrder {
+    /// The order of the piece file
+    File,
+    /// The pieces with the fewest placements that fit the cell first, ties in file order
+    #[default]
+    FewestPlacements,
+}
+
+impl std::str::FromStr for PieceOrder {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "file" => Ok(Self::File),
+            "fewest-placements" => Ok(Self::FewestPlacements),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Pieces in the order to try them at a node branching on `cell`, None for file order
+fn order_pieces(
+    cell: usize,
+    state: BitCube,
+    used_pieces: u64,
+    placements: &Placements,
+    order: PieceOrder,
+) -> Option<Vec<usize>> {
+    if order == PieceOrder::File {
+        return None;
+    }
+    let mut pieces: Vec<usize> = (0..placements.piece_placements.len()).collect();
+    // Used pieces are skipped by the search, so where they end up doesn't matter
+    pieces.sort_by_cached_key(|piece| {
+        if used_pieces & (1 << piece) != 0 {
+            return 0;
+        }
+        placements
+            .for_cell(cell, *piece)
+            .filter(|p| p.is_disjoint(state))
+            .count()
+    });
+    Some(pieces)
+}
+
+/// Strategy for picking the empty cell the search branches on
+///
+/// Every choice at a node covers the selected cell, so any empty cell gives a complete
+/// search, but the number of nodes visited depends on it. `CellHeuristic` implements the
+/// built in strategies, implement this to try others without touching the search.
+pub trait CellSelector {
+    /// Cell to branch on in the given state, which has at least one empty cell
+    fn select(&self, state: BitCube, used_pieces: u64, placements: &Placements) -> usize;
+}
+
+impl CellSelector for CellHeuristic {
+    fn select(&self, state: BitCube, used_pieces: u64, placements: &Placements) -> usize {
+        choose_cell(state, used_pieces, placements, *self)
+    }
+}
+
+/// Cell for the search to branch on in the given state, which must have an empty cell
+pub fn choose_cell(
+    state: BitCube,
+    used_pieces: u64,
+    placements: &Placements,
+    heuristic: CellHeuristic,
+) -> usize {
+    let lowest = state.trailing_ones() as usize;
+    match heuristic {
+        CellHeuristic::LowestBit => return lowest,
+        CellHeuristic::CornerFirst => return corner_cell(state, &placements.puzzle),
+        CellHeuristic::MostConstrained => {}
+    }
+
+    let mut best = (usize::MAX, lowest);
+    let mut empty = !state & placements.puzzle.mask();
+    while !empty.is_empty() {
+        let bit_index = empty.trailing_zeros() as usize;
+        empty &= !empty.lowest_bit();
+        let mut count = 0;
+        for piece in 0..placements.piece_placements.len() {
+            if used_pieces & (1 << piece) != 0 {
+                continue;
+            }
+            count += placements
+                .for_cell(bit_index, piece)
+                .filter(|p| p.is_disjoint(state))
+                .count();
+            // Can't beat the best cell so far
+            if count >= best.0 {
+                break;
+            }
+        }
+        if count < best.0 {
+            best = (count, bit_index);
+            // A cell nothing fits is a dead end, a cell with one choice is forced
+            if count <= 1 {
+                break;
+            }
+        }
+    }
+    best.1
+}
+
+/// Empty cell of the state with the fewest steps to a corner of the box, lowest bit first
+fn corner_cell(state: BitCube, puzzle: &Puzzle) -> usize {
+    let mut best = (usize::MAX, 0);
+    let mut empty = !state & puzzle.mask();
+    while !empty.is_empty() {
+        let bit_index = empty.trailing_zeros() as usize;
+        empty &= !empty.lowest_bit();
+        let (layer, row) = (puzzle.dims[1] * puzzle.dims[2], puzzle.dims[2]);
+        let coords = [
+            bit_index / layer,
+            bit_index / row % puzzle.dims[1],
+            bit_index % row,
+        ];
+        let distance: usize = coords
+            .iter()
+            .zip(puzzle.dims)
+            .map(|(c, size)| (*c).min(size - 1 - c))
+            .sum();
+        if distance < best.0 {
+            best = (distance, bit_index);
+        }
+    }
+    best.1
+}
+
+/// Options controlling the search
+#[derive(Clone)]
+pub struct SearchOptions {
+    /// Number of pieces to place before recording the state, all pieces for full solutions
+    pub max_depth: usize,
+    /// Stop the search once this many solutions have been found
+    pub max_solutions: Option<usize>,
+    /// Stop the search once this many unique solutions have been found, which needs
+    /// `Stats::unique_solutions` to be tracked. Not supported by `SolutionIter`.
+    pub max_unique_solutions: Option<usize>,
+    /// Prune states that `quick_feasible` rules out
+    pub quick_feasible: bool,
+    /// Prune states that `regions_feasible` rules out, implied by `quick_feasible`
+    pub prune_regions: bool,
+    /// Keep the number of placements that fit in each empty cell up to date as pieces are
+    /// placed, and prune states where one of them drops to zero. Catches the same dead ends
+    /// as the cell coverage of `quick_feasible` without scanning every cell at each node.
+    /// Not supported by `Dlx::search` and `SolutionIter`.
+    pub forward_checking: bool,
+    /// Only search these top level branches, indexed by the order they are tried in
+    pub branches: Option<std::ops::Range<usize>>,
+    /// How to pick the cell to branch on, usually a `CellHeuristic`
+    pub cell_selector: std::sync::Arc<dyn CellSelector + Send + Sync>,
+    /// Order to try the pieces in at each node
+    pub piece_order: PieceOrder,
+    /// Try the pieces and placements at each node in a random order picked from this seed
+    /// and the state, instead of in order. Not supported by `Dlx::search` and `SolutionIter`.
+    pub random_seed: Option<u64>,
+    /// (piece, placement) pairs, branches placing one of these pieces anywhere else or
+    /// covering its placement with another piece are pruned. Unlike `fix_placements` the
+    /// placements are left as they are. Not supported by `Dlx::search`.
+    pub required: Vec<(usize, BitCube)>,
+}
+
+impl SearchOptions {
+    /// Whether the search has found as many solutions as it should
+    fn reached_max_solutions(&self, stats: &Stats) -> bool {
+        self.max_solutions
+            .is_some_and(|max| stats.num_solutions >= max)
+            || self.max_unique_solutions.is_some_and(|max| {
+                stats
+                    .unique_solutions
+                    .as_ref()
+                    .is_some_and(|unique_solutions| unique_solutions.len() >= max)
+            })
+    }
+    /// Random number generator for the order of the choices at a node, if it is randomized
+    /// The order only depends on the node, so that shards, threads and checkpoints agree on it
+    fn node_rng(&self, state: BitCube, used_pieces: u64) -> Option<Rng> {
+        let seed = self.random_seed?;
+        let mut rng = Rng::new(
+            seed ^ state.0 as u64
+                ^ ((state.0 >> 64) as u64).rotate_left(32)
+                ^ used_pieces.rotate_left(16),
+        );
+        // Spread nearby seeds before the first draw
+        for _ in 0..4 {
+            rng.next();
+        }
+        Some(rng)
+    }
+    /// Whether the placement of the piece is allowed by `required`
+    fn allowed(&self, piece: usize, placement: BitCube) -> bool {
+        self.required
+            .iter()
+            .all(|&(required, mask)| required != piece || mask == placement)
+    }
+    /// Whether the state can still lead to a solution, as far as the enabled pruning can tell
+    fn feasible(&self, state: BitCube, used_pieces: u64, placements: &Placements) -> bool {
+        // The cells of a required placement have to stay free until its piece is placed
+        if self
+            .required
+            .iter()
+            .any(|&(piece, mask)| used_pieces & (1 << piece) == 0 && !mask.is_disjoint(state))
+        {
+            return false;
+        }
+        if self.quick_feasible {
+            quick_feasible(state, used_pieces, placements)
+        } else if self.prune_regions {
+            regions_feasible(state, used_pieces, placements)
+        } else {
+            true
+        }
+    }
+}
+
+/// Node of the search in `search`, with the cursor to the next choice to try at the node
+struct SearchNode {
+    state: BitCube,
+    used_pieces: u64,
+    /// Depth in the search tree, the pieces placed and the cells left empty so far
+    depth: usize,
+    /// Number of cells left empty so far
+    holes: usize,
+    /// Cell the node branches on
+    cell: usize,
+    /// Number of children the progress estimate is split between
+    num_children: usize,
+    /// Number of viable choices tried so far, to pick the top level branches
+    choices: usize,
+    /// Random order of the choices, if it is randomized, see `SearchOptions::node_rng`
+    rng: Option<Rng>,
+    piece_order: Option<Vec<usize>>,
+    /// Whether the child searched last is counted in the candidate counts of forward
+    /// checking, to undo when the search comes back to the node
+    counted: bool,
+    /// The piece being tried, as an index into the piece order, with the next of its
+    /// placements to try, an index into `Placements::candidates` or into `shuffled`
+    piece_index: usize,
+    placement_index: usize,
+    shuffled: Vec<BitCube>,
+    /// Piece placed in the child searched last, with the number of solutions before it
+    placed: Option<(usize, usize)>,
+    /// Whether leaving the cell empty has been tried
+    hole_tried: bool,
+}
+
+/// Choice at a node of the search
+enum Choice {
+    Place(usize, BitCube),
+    /// Leave the cell empty
+    Hole,
+}
+
+/// Outcome of visiting a node of the search
+enum Visit {
+    /// Stop the whole search
+    Stop,
+    /// Nothing to search below the node
+    Done,
+    /// Search the children of the node
+    Branch(SearchNode),
+}
+
+impl SearchNode {
+    /// Next viable choice, moving the cursor past it
+    fn next_choice(
+        &mut self,
+        placements: &Placements,
+        picks: &mut Solution,
+        options: &SearchOptions,
+        stats: &Stats,
+    ) -> Option<Choice> {
+        let num_pieces = picks.0.len();
+        while self.piece_index < num_pieces {
+            let piece = self
+                .piece_order
+                .as_ref()
+                .map_or(self.piece_index, |order| order[self.piece_index]);
+            if self.used_pieces & (1 << piece) == 0 {
+                if self.placement_index == 0 {
+                    if let Some(rng) = &mut self.rng {
+                        self.shuffled.clear();
+                        self.shuffled.extend(placements.for_cell(self.cell, piece));
+                        rng.shuffle(&mut self.shuffled);
+                    }
+                }
+                // Only the candidates covering the cell are placements for it
+                let (candidates, filtered) = match self.rng {
+                    Some(_) => (&self.shuffled[..], false),
+                    None => (placements.candidates(self.cell, piece), true),
+                };
+                while let Some(&placement) = candidates.get(self.placement_index) {
+                    self.placement_index += 1;
+                    if (filtered && !placement.test_bit(self.cell))
+                        || !placement.is_disjoint(self.state)
+                        || !options.allowed(piece, placement)
+                    {
+                        continue;
+                    }
+                    self.choices += 1;
+                    if self.depth == 0
+                        && options
+                            .branches
+                            .as_ref()
+                            .is_some_and(|branches| !branches.contains(&(self.choices - 1)))
+                    {
+                        continue;
+                    }
+                    // Skip the subtrees that were completed before the checkpoint
+                    if stats
+                        .resume
+                        .as_ref()
+                        .is_some_and(|resume| resume.0[piece] != placement)
+                    {
+                        continue;
+                    }
+                    return Some(Choice::Place(piece, placement));
+                }
+                // Unused pieces are kept empty so that picks can be reported as is
+                picks.0[piece] = BitCube::default();
+            }
+            self.piece_index += 1;
+            self.placement_index = 0;
+        }
+        // The last choice is to leave the cell empty, while the pieces can't fill the box
+        if !self.hole_tried && self.holes < placements.num_holes {
+            self.hole_tried = true;
+            self.choices += 1;
+            if self.depth != 0
+                || options
+                    .branches
+                    .as_ref()
+                    .is_none_or(|branches| branches.contains(&(self.choices - 1)))
+            {
+                return Some(Choice::Hole);
+            }
+        }
+        None
+    }
+}
+
+/// Visit a node of the search, recording solutions and dead ends
+#[allow(clippy::too_many_arguments)]
+fn visit(
+    state: BitCube,
+    used_pieces: u64,
+    placements: &Placements,
+    picks: &mut Solution,
+    options: &SearchOptions,
+    candidates: Option<&CandidateCounts>,
+    stats: &mut Stats,
+    on_solution: &mut impl FnMut(&Solution),
+) -> Visit {
+    stats.print();
+    if stats.time_is_up() {
+        return Visit::Stop;
+    }
+    let num_placed = used_pieces.count_ones() as usize;
+    // Every cell left empty is a level of the search tree as well
+    let holes = placements.holes(state, used_pieces);
+    let depth = num_placed + holes;
+    if let Some(resume) = &stats.resume {
+        // Reached the node to resume at, search normally from here on
+        if resume.0.iter().filter(|p| !p.is_empty()).count() == depth {
+            stats.resume = None;
+        }
+    } else if stats.checkpoint_due() {
+        stats.stop_for_checkpoint(picks);
+        return Visit::Stop;
+    }
+    if num_placed == options.max_depth {
+        // Slows down things quite a lot, but prints each solution
+        // print_solution(picks);
+        // println!();
+        debug_assert!(
+            num_placed < placements.puzzle.num_pieces
+                || validate_solution(&placements.puzzle, picks)
+        );
+        stats.success(&placements.puzzle, picks);
+        stats.complete(depth, 0);
+        on_solution(picks);
+        return if options.reached_max_solutions(stats) {
+            Visit::Stop
+        } else {
+            Visit::Done
+        };
+    }
+
+    if !options.feasible(state, used_pieces, placements)
+        || candidates
+            .is_some_and(|candidates| !candidates.feasible(state, placements.num_holes - holes))
+    {
+        stats.fail();
+        stats.complete(depth, 0);
+        return Visit::Done;
+    }
+
+    let cell = options.cell_selector.select(state, used_pieces, placements);
+
+    // The number of children is needed up front to weight them for the progress estimate
+    let mut num_children = 0;
+    if depth < PROGRESS_DEPTH {
+        num_children = placements.fitting(cell, state, used_pieces).count()
+            + usize::from(holes < placements.num_holes);
+        if let (0, Some(branches)) = (depth, &options.branches) {
+            num_children = branches
+                .end
+                .min(num_children)
+                .saturating_sub(branches.start);
+        }
+        stats.split_progress(depth, num_children);
+    }
+
+    let mut rng = options.node_rng(state, used_pieces);
+    let piece_order = match &mut rng {
+        Some(rng) => {
+            let mut order: Vec<usize> = (0..picks.0.len()).collect();
+            rng.shuffle(&mut order);
+            Some(order)
+        }
+        None => order_pieces(cell, state, used_pieces, placements, options.piece_order),
+    };
+    Visit::Branch(SearchNode {
+        state,
+        used_pieces,
+        depth,
+        holes,
+        cell,
+        num_children,
+        choices: 0,
+        rng,
+        piece_order,
+        counted: false,
+        piece_index: 0,
+        placement_index: 0,
+        shuffled: Vec::new(),
+        placed: None,
+        hole_tried: false,
+    })
+}
+
+/// Search algorithm
+/// state: bit mask of the current state of the cube
+/// used_pieces: bit mask of the pieces that have been used
+/// placements: placements of each piece, indexed by the bits they cover
+/// picks: stack for keeping track of picked pieces (piece_id, placement)
+/// on_solution: called for every solution found
+/// Returns true if the search was stopped early
+///
+/// The nodes from the starting one down to the one being searched are kept on an
+/// explicit stack, so that deep searches in large boxes don't need deep recursion.
+pub fn search(
+    state: BitCube,
+    used_pieces: u64,
+    placements: &Placements,
+    picks: &mut Solution,
+    options: &SearchOptions,
+    stats: &mut Stats,
+    on_solution: &mut impl FnMut(&Solution),
+) -> bool {
+    let mut stack = Vec::with_capacity(placements.puzzle.num_bits());
+    let mut candidates = options
+        .forward_checking
+        .then(|| CandidateCounts::new(state, used_pieces, placements));
+    match visit(
+        state,
+        used_pieces,
+        placements,
+        picks,
+        options,
+        candidates.as_ref(),
+        stats,
+        on_solution,
+    ) {
+        Visit::Stop => return true,
+        Visit::Done => return false,
+        Visit::Branch(node) => stack.push(node),
+    }
+    while let Some(node) = stack.last_mut() {
+        if let (Some(candidates), true) = (&mut candidates, node.counted) {
+            // The child placed the piece of `placed` at its pick, or left the cell empty
+            let (piece, mask) = match node.placed {
+                Some((piece, _)) => (Some(piece), picks.0[piece]),
+                None => (None, BitCube(1 << node.cell)),
+            };
+            candidates.update(node.state, node.used_pieces, piece, mask, placements, false);
+            node.counted = false;
+        }
+        if let Some((piece, num_solutions)) = node.placed.take() {
+            stats.placed(piece, stats.num_solutions == num_solutions);
+        }
+        let (piece, mask) = match node.next_choice(placements, picks, options, stats) {
+            Some(Choice::Place(piece, placement)) => {
+                picks.0[piece] = placement;
+                node.placed = Some((piece, stats.num_solutions));
+                (Some(piece), placement)
+            }
+            Some(Choice::Hole) => (None, BitCube(1 << node.cell)),
+            None => {
+                stats.branch(node.depth, node.choices);
+                stats.complete(node.depth, node.num_children);
+                stats.fail();
+                stack.pop();
+                continue;
+            }
+        };
+        if let Some(candidates) = &mut candidates {
+            candidates.update(node.state, node.used_pieces, piece, mask, placements, true);
+            node.counted = true;
+        }
+        let state = node.state | mask;
+        let used_pieces = node.used_pieces | piece.map_or(0, |piece| 1 << piece);
+        match visit(
+            state,
+            used_pieces,
+            placements,
+            picks,
+            options,
+            candidates.as_ref(),
+            stats,
+            on_solution,
+        ) {
+            Visit::Stop => return true,
+            Visit::Done => {}
+            Visit::Branch(node) => stack.push(node),
+        }
+    }
+    false
+}
+
+/// Node of the search in `SolutionIter`, with the next choice to try at the node
+struct Frame {
+    state: BitCube,
+    used_pieces: u64,
+    /// Cell the node branches on
+    cell: usize,
+    /// Order of the pieces, None for file order, see `SearchOptions::piece_order`
+    piece_order: Option<Vec<usize>>,
+    /// Piece, as an index into the piece order, and index into its
+    /// `Placements::candidates` of the next placement to try
+    piece: usize,
+    placement_index: usize,
+    /// Number of viable choices tried so far, to pick the top level branches
+    choices: usize,
+}
+
+/// Iterator over the solutions of the search, finding the next solution on each call to `next`
+///
+/// Finds the same solutions in the same order as `search` with the same options, but keeps
+/// the nodes of the search on an explicit stack so that it can be suspended after each
+/// solution. Stats, progress and checkpoints are only kept by `search`. Cells are never
+/// left empty, so there are no solutions when the pieces have fewer cells than the box.
+pub struct SolutionIter<'a> {
+    placements: &'a Placements,
+    options: SearchOptions,
+    picks: Solution,
+    /// The nodes from the root to the one being searched
+    stack: Vec<Frame>,
+    /// Whether the root node has been visited, the search is over once the stack is empty
+    started: bool,
+    num_solutions: usize,
+}
+
+impl<'a> SolutionIter<'a> {
+    pub fn new(placements: &'a Placements, options: SearchOptions) -> Self {
+        Self {
+            placements,
+            options,
+            picks: Solution(vec![BitCube::default(); placements.puzzle.num_pieces]),
+            stack: Vec::new(),
+            started: false,
+            num_solutions: 0,
+        }
+    }
+    /// Start searching the node, unless it's ruled out by the pruning
+    fn push(&mut self, state: BitCube, used_pieces: u64) {
+        if !self.options.feasible(state, used_pieces, self.placements) {
+            return;
+        }
+        let cell = self
+            .options
+            .cell_selector
+            .select(state, used_pieces, self.placements);
+        let piece_order = order_pieces(
+            cell,
+            state,
+            used_pieces,
+            self.placements,
+            self.options.piece_order,
+        );
+        self.stack.push(Frame {
+            state,
+            used_pieces,
+            cell,
+            piece_order,
+            piece: 0,
+            placement_index: 0,
+            choices: 0,
+        });
+    }
+    /// Next viable choice at the top node, moving its cursor past it
+    fn next_choice(&mut self) -> Option<(usize, BitCube)> {
+        let frame = self.stack.last_mut()?;
+        while frame.piece < self.picks.0.len() {
+            let piece = frame
+                .piece_order
+                .as_ref()
+                .map_or(frame.piece, |order| order[frame.piece]);
+            if frame.used_pieces & (1 << piece) == 0 {
+                let candidates = self.placements.candidates(frame.cell, piece);
+                while let Some(&placement) = candidates.get(frame.placement_index) {
+                    frame.placement_index += 1;
+                    if !placement.test_bit(frame.cell)
+                        || !placement.is_disjoint(frame.state)
+                        || !self.options.allowed(piece, placement)
+                    {
+                        continue;
+                    }
+                    frame.choices += 1;
+                    if frame.used_pieces == 0
+                        && self
+                            .options
+                            .branches
+                            .as_ref()
+                            .is_some_and(|branches| !branches.contains(&(frame.choices - 1)))
+                    {
+                        continue;
+                    }
+                    return Some((piece, placement));
+                }
+                // Unused pieces are kept empty so that picks can be reported as is
+                self.picks.0[piece] = BitCube::default();
+            }
+            frame.piece += 1;
+            frame.placement_index = 0;
+        }
+        None
+    }
+}
+
+impl Iterator for SolutionIter<'_> {
+    type Item = Solution;
+
+    fn next(&mut self) -> Option<Solution> {
+        if self
+            .options
+            .max_solutions
+            .is_some_and(|max| self.num_solutions >= max)
+        {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            // The root is the only state with no pieces placed
+            if self.options.max_depth == 0 {
+                self.num_solutions += 1;
+                return Some(self.picks.clone());
+            }
+            self.push(BitCube::default(), 0);
+        }
+        while let Some(frame) = self.stack.last() {
+            let (state, used_pieces) = (frame.state, frame.used_pieces);
+            let Some((piece, placement)) = self.next_choice() else {
+                self.stack.pop();
+                continue;
+            };
+            self.picks.0[piece] = placement;
+            let (state, used_pieces) = (state | placement, used_pieces | 1 << piece);
+            let depth = used_pieces.count_ones() as usize;
+            if depth == self.options.max_depth {
+                debug_assert!(
+                    depth < self.picks.0.len()
+                        || validate_solution(&self.placements.puzzle, &self.picks)
+                );
+                self.num_solutions += 1;
+                return Some(self.picks.clone());
+            }
+            self.push(state, used_pieces);
+        }
+        None
+    }
+}
+
+/// Run the search on multiple threads, each thread taking one top level branch at a time
+/// Returns the solutions in the same order as `search` would find them, if `keep_solutions`
+/// is set, and whether the search was stopped early
+pub fn parallel_search(
+    placements: &Placements,
+    options: &SearchOptions,
+    num_branches: usize,
+    num_threads: usize,
+    keep_solutions: bool,
+    stats: &mut Stats,
+) -> (Vec<Solution>, bool) {
+    let branches = options.branches.clone().unwrap_or(0..num_branches);
+    let next_branch = std::sync::atomic::AtomicUsize::new(branches.start);
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    let totals = std::sync::Mutex::new(stats);
+
+    let mut results: Vec<(usize, Vec<Solution>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let branch = next_branch.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if branch >= branches.end || stop.load(std::sync::atomic::Ordering::Relaxed)
+                        {
+                            break;
+                        }
+
+                        let branch_options = SearchOptions {
+                            branches: Some(branch..branch + 1),
+                            ..options.clone()
+                        };
+                        let mut branch_stats = totals
+                            .lock()
+                            .unwrap()
+                            .for_thread(1.0 / branches.len() as f64);
+                        let mut branch_solutions = Vec::new();
+                        let stopped = search(
+                            BitCube::default(),
+                            0,
+                            placements,
+                            &mut Solution(vec![BitCube::default(); placements.puzzle.num_pieces]),
+                            &branch_options,
+                            &mut branch_stats,
+                            &mut |solution| {
+                                if keep_solutions {
+                                    branch_solutions.push(solution.clone())
+                                }
+                            },
+                        );
+
+                        let mut totals = totals.lock().unwrap();
+                        totals.add(&branch_stats);
+                        totals.print();
+                        if stopped || options.reached_max_solutions(&totals) {
+                            stop.store(true, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        results.push((branch, branch_solutions));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    results.sort_by_key(|(branch, _)| *branch);
+    let mut solutions: Vec<Solution> = results
+        .into_iter()
+        .flat_map(|(_, solutions)| solutions)
+        .collect();
+    if let Some(max) = options.max_solutions {
+        solutions.truncate(max);
+    }
+    (solutions, stop.into_inner())
+}
+
+/// Number of solutions a thread of `parallel_unique_search` collects before merging them
+const UNIQUE_CHUNK: usize = 1024;
+
+/// Run the search on multiple threads like `parallel_search`, only keeping the solutions
+/// that are unique up to rotation, and reflection if there's a piece map for reflections
+///
+/// Each thread collects the solutions it finds in a buffer of at most `UNIQUE_CHUNK`
+/// solutions, which is merged into a shared map from canonical key to solution whenever it
+/// fills up and at the end of each branch. Only one solution per canonical key is kept in
+/// memory instead of every solution found. Of the solutions with the same key, the one
+/// `search` would find first is kept, so the result is the same as `filter_unique_solutions`
+/// of the solutions of `parallel_search`, in the same order.
+/// Returns the unique solutions and whether the search was stopped early.
+pub fn parallel_unique_search(
+    placements: &Placements,
+    options: &SearchOptions,
+    num_branches: usize,
+    num_threads: usize,
+    mirror: Option<&[usize]>,
+    stats: &mut Stats,
+) -> (Vec<Solution>, bool) {
+    let puzzle = &placements.puzzle;
+    let branches = options.branches.clone().unwrap_or(0..num_branches);
+    let next_branch = std::sync::atomic::AtomicUsize::new(branches.start);
+    let stop = std::sync::atomic::AtomicBool::new(false);
+    let totals = std::sync::Mutex::new(stats);
+    // Solution for each canonical key, with its branch and index in the branch
+    let unique = std::sync::Mutex::new(std::collections::HashMap::new());
+    let merge = |chunk: &mut Vec<([u8; MAX_CELLS], (usize, usize), Solution)>| {
+        let mut unique = unique.lock().unwrap();
+        for (key, order, solution) in chunk.drain(..) {
+            let kept = unique.entry(key).or_insert((order, solution.clone()));
+            if order < kept.0 {
+                *kept = (order, solution);
+            }
+        }
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| {
+                let mut chunk = Vec::with_capacity(UNIQUE_CHUNK);
+                loop {
+                    let branch = next_branch.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    if branch >= branches.end || stop.load(std::sync::atomic::Ordering::Relaxed) {
+                        break;
+                    }
+
+                    let branch_options = SearchOptions {
+                        branches: Some(branch..branch + 1),
+                        ..options.clone()
+                    };
+                    let mut branch_stats = totals
+                        .lock()
+                        .unwrap()
+                        .for_thread(1.0 / branches.len() as f64);
+                    let mut index = 0;
+                    let stopped = search(
+                        BitCube::default(),
+                        0,
+                        placements,
+                        &mut Solution(vec![BitCube::default(); puzzle.num_pieces]),
+                        &branch_options,
+                        &mut branch_stats,
+                        &mut |solution| {
+                            let key = canonical_key(puzzle, solution, mirror.is_some());
+                            chunk.push((key, (branch, index), solution.clone()));
+                            index += 1;
+                            if chunk.len() == UNIQUE_CHUNK {
+                                merge(&mut chunk);
+                            }
+                        },
+                    );
+                    merge(&mut chunk);
+
+                    let mut totals = totals.lock().unwrap();
+                    totals.add(&branch_stats);
+                    totals.print();
+                    if stopped || options.reached_max_solutions(&totals) {
+                        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+
+    let mut unique: Vec<_> = unique.into_inner().unwrap().into_values().collect();
+    unique.sort_by_key(|(order, _)| *order);
+    let solutions = unique.into_iter().map(|(_, solution)| solution).collect();
+    (solutions, stop.into_inner())
+}
+
+/// State of an interrupted search, enough to resume it where it stopped
+pub struct Checkpoint {
+    /// Picks of the node to resume the search at
+    pub picks: Solution,
+    pub num_permutations: usize,
+    pub num_solutions: usize,
+    pub progress: f64,
+    /// Solutions kept so far
+    pub solutions: Vec<Solution>,
+}
+
+/// Write a checkpoint, the header lines followed by the solutions as written by
+/// `write_solutions`
+pub fn write_checkpoint(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    checkpoint: &Checkpoint,
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    writeln!(stream, "# Checkpoint")?;
+    writeln!(stream, "Permutations: {}", checkpoint.num_permutations)?;
+    writeln!(stream, "Solutions: {}", checkpoint.num_solutions)?;
+    writeln!(stream, "Progress: {}", checkpoint.progress)?;
+    write!(stream, "Picks:")?;
+    for placement in &checkpoint.picks.0 {
+        write!(stream, " {:x}", placement)?;
+    }
+    writeln!(stream)?;
+    writeln!(stream)?;
+    write_solutions(puzzle, pieces, &checkpoint.solutions, stream)
+}
+
+/// Read a checkpoint written by `write_checkpoint`
+pub fn read_checkpoint(
+    puzzle: &Puzzle,
+    path: &std::path::Path,
+) -> Result<Checkpoint, std::io::Error> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let contents = std::fs::read_to_string(path)?;
+    let field = |name: &str| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .ok_or_else(|| invalid(format!("Missing {} in {}", name, path.display())))
+    };
+    let number = |name: &str| {
+        field(name)?
+            .parse::<usize>()
+            .map_err(|_| invalid(format!("Invalid {} in {}", name, path.display())))
+    };
+
+    let picks = field("Picks")?
+        .split_whitespace()
+        .map(|placement| u128::from_str_radix(placement, 16).map(BitCube))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid(format!("Invalid Picks in {}", path.display())))?;
+    if picks.len() != puzzle.num_pieces {
+        return Err(invalid(format!(
+            "Expected {} picks in {}, got {}",
+            puzzle.num_pieces,
+            path.display(),
+            picks.len()
+        )));
+    }
+    Ok(Checkpoint {
+        picks: Solution(picks),
+        num_permutations: number("Permutations")?,
+        num_solutions: number("Solutions")?,
+        progress: field("Progress")?
+            .parse()
+            .map_err(|_| invalid(format!("Invalid Progress in {}", path.display())))?,
+        solutions: read_solutions(puzzle, path)?,
+    })
+}
+
+/// Hash of the box dimensions and the pieces, the key of cached placements
+///
+/// 64 bit FNV-1a, which unlike the hasher of the standard library is the same in every
+/// build, so that cache files can be found again.
+pub fn pieces_hash(puzzle: &Puzzle, pieces: &[BitCube]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let dims = puzzle.dims.iter().map(|d| *d as u128);
+    for value in dims.chain(pieces.iter().map(|piece| piece.0)) {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Write the placements of every piece, so that they can be read back instead of generated
+///
+/// The box and the pieces are written first, to tell whether the placements are for the
+/// same puzzle when reading them, followed by a line of placements per piece.
+pub fn write_placements(
+    puzzle: &Puzzle,
+    pieces: &[BitCube],
+    piece_placements: &[Vec<BitCube>],
+    stream: &mut impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    writeln!(stream, "# Placements")?;
+    let [size_x, size_y, size_z] = puzzle.dims;
+    writeln!(stream, "Box: {}x{}x{}", size_x, size_y, size_z)?;
+    write!(stream, "Pieces:")?;
+    for piece in pieces {
+        write!(stream, " {:x}", piece)?;
+    }
+    writeln!(stream)?;
+    for placements in piece_placements {
+        let placements: Vec<_> = placements.iter().map(|p| format!("{:x}", p)).collect();
+        writeln!(stream, "{}", placements.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Read placements written by `write_placements`
+///
+/// Returns None if the placements were written for another box or other pieces.
+pub fn read_placements(
+    puzzle: &Puzzle,
+    pieces: &[BitCube],
+    path: &std::path::Path,
+) -> Result<Option<Vec<Vec<BitCube>>>, std::io::Error> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let contents = std::fs::read_to_string(path)?;
+    let hex = |line: &str| {
+        line.split_whitespace()
+            .map(|placement| u128::from_str_radix(placement, 16).map(BitCube))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid(format!("Invalid placements in {}", path.display())))
+    };
+
+    let mut lines = contents.lines().filter(|line| !line.starts_with('#'));
+    let [size_x, size_y, size_z] = puzzle.dims;
+    let dims = lines.next().and_then(|line| line.strip_prefix("Box: "));
+    let cached_pieces = lines.next().and_then(|line| line.strip_prefix("Pieces:"));
+    let (Some(dims), Some(cached_pieces)) = (dims, cached_pieces) else {
+        return Err(invalid(format!("Missing header in {}", path.display())));
+    };
+    if dims != format!("{}x{}x{}", size_x, size_y, size_z) || hex(cached_pieces)? != pieces {
+        return Ok(None);
+    }
+    let piece_placements = lines.map(hex).collect::<Result<Vec<_>, _>>()?;
+    if piece_placements.len() != pieces.len() {
+        return Err(invalid(format!(
+            "Expected placements of {} pieces in {}, got {}",
+            pieces.len(),
+            path.display(),
+            piece_placements.len()
+        )));
+    }
+    Ok(Some(piece_placements))
+}
+
+/// Exact cover matrix for Knuth's Algorithm X, linked as dancing links
+///
+/// There is a column for every cell of the cube followed by one for every piece, and a row
+/// for every placement covering the cells of the placement and the column of its piece.
+/// Node 0 is the root and nodes 1..=num_columns are the column headers. Every cell has to
+/// be covered, so the pieces need as many cells as the cube.
+pub struct Dlx {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    /// Column header of each node
+    column: Vec<usize>,
+    /// Row of each node, unused for the root and headers
+    row: Vec<usize>,
+    /// Number of nodes in each column, indexed by header node
+    size: Vec<usize>,
+    /// Piece and placement of each row
+    rows: Vec<(usize, BitCube)>,
+}
+
+impl Dlx {
+    pub fn new(placements: &Placements) -> Self {
+        let puzzle = &placements.puzzle;
+        let num_columns = puzzle.num_bits() + puzzle.num_pieces;
+        let mut dlx = Self {
+            left: (0..=num_columns)
+                .map(|i| if i == 0 { num_columns } else { i - 1 })
+                .collect(),
+            right: (0..=num_columns)
+                .map(|i| (i + 1) % (num_columns + 1))
+                .collect(),
+            up: (0..=num_columns).collect(),
+            down: (0..=num_columns).collect(),
+            column: (0..=num_columns).collect(),
+            row: vec![0; num_columns + 1],
+            size: vec![0; num_columns + 1],
+            rows: Vec::new(),
+        };
+
+        for (piece, piece_placements) in placements.piece_placements.iter().enumerate() {
+            for placement in piece_placements {
+                let row = dlx.rows.len();
+                dlx.rows.push((piece, *placement));
+
+                let columns = (0..puzzle.num_bits())
+                    .filter(|bit_index| placement.test_bit(*bit_index))
+                    .chain(std::iter::once(puzzle.num_bits() + piece));
+                let first = dlx.column.len();
+                for column in columns {
+                    let node = dlx.column.len();
+                    let header = column + 1;
+                    // Append to the bottom of the column and the end of the row
+                    dlx.up.push(dlx.up[header]);
+                    dlx.down.push(header);
+                    let bottom = dlx.up[header];
+                    dlx.down[bottom] = node;
+                    dlx.up[header] = node;
+                    dlx.left.push(if node == first { node } else { node - 1 });
+                    dlx.right.push(first);
+                    if node != first {
+                        dlx.right[node - 1] = node;
+                        dlx.left[first] = node;
+                    }
+                    dlx.column.push(header);
+                    dlx.row.push(row);
+                    dlx.size[header] += 1;
+                }
+            }
+        }
+        dlx
+    }
+
+    fn cover(&mut self, column: usize) {
+        self.right[self.left[column]] = self.right[column];
+        self.left[self.right[column]] = self.left[column];
+        let mut i = self.down[column];
+        while i != column {
+            let mut j = self.right[i];
+            while j != i {
+                self.up[self.down[j]] = self.up[j];
+                self.down[self.up[j]] = self.down[j];
+                self.size[self.column[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, column: usize) {
+        let mut i = self.up[column];
+        while i != column {
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.column[j]] += 1;
+                self.up[self.down[j]] = j;
+                self.down[self.up[j]] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+        self.right[self.left[column]] = column;
+        self.left[self.right[column]] = column;
+    }
+
+    /// Algorithm X, always branching on the column with the fewest rows
+    /// Takes the same arguments as `search`, apart from the options only `max_solutions`
+    /// and `max_unique_solutions` are supported. Returns true if the search was stopped early.
+    pub fn search(
+        &mut self,
+        puzzle: &Puzzle,
+        picks: &mut Solution,
+        options: &SearchOptions,
+        stats: &mut Stats,
+        on_solution: &mut impl FnMut(&Solution),
+    ) -> bool {
+        stats.print();
+        if stats.time_is_up() {
+            return true;
+        }
+        if self.right[0] == 0 {
+            debug_assert!(validate_solution(puzzle, picks));
+            stats.success(puzzle, picks);
+            on_solution(picks);
+            return options.reached_max_solutions(stats);
+        }
+
+        let mut column = self.right[0];
+        let mut j = self.right[column];
+        while j != 0 {
+            if self.size[j] < self.size[column] {
+                column = j;
+            }
+            j = self.right[j];
+        }
+
+        self.cover(column);
+        let mut choices = 0;
+        let mut r = self.down[column];
+        while r != column {
+            choices += 1;
+            let (piece, placement) = self.rows[self.row[r]];
+            picks.0[piece] = placement;
+            let mut j = self.right[r];
+            while j != r {
+                self.cover(self.column[j]);
+                j = self.right[j];
+            }
+
+            let num_solutions = stats.num_solutions;
+            let stopped = self.search(puzzle, picks, options, stats, on_solution);
+            stats.placed(piece, stats.num_solutions == num_solutions);
+
+            let mut j = self.left[r];
+            while j != r {
+                self.uncover(self.column[j]);
+                j = self.left[j];
+            }
+            picks.0[piece] = BitCube::default();
+            if stopped {
+                // Leave the matrix as it was for the next search
+                self.uncover(column);
+                return true;
+            }
+            r = self.down[r];
+        }
+        self.uncover(column);
+
+        let depth = picks.0.iter().filter(|p| !p.is_empty()).count();
+        stats.branch(depth, choices);
+        stats.fail();
+        false
+    }
+}
+
+/// Small xorshift pseudo random number generator, seeded for reproducible runs
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Self(seed.max(1))
+    }
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    /// Random number in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    /// Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// Create an easier puzzle by removing `num_removed` random pieces from a solution
+/// Returns the partially filled cube and the ids of the removed pieces
+pub fn make_sub_puzzle(
+    solution: &Solution,
+    num_removed: usize,
+    rng: &mut Rng,
+) -> (Solution, Vec<usize>) {
+    let num_pieces = solution.0.len();
+    let mut ids: Vec<usize> = (0..num_pieces).collect();
+    let num_removed = num_removed.min(num_pieces);
+    // Partial Fisher-Yates shuffle, the first num_removed ids are the picked ones
+    for i in 0..num_removed {
+        let j = i + rng.below(num_pieces - i);
+        ids.swap(i, j);
+    }
+    let mut removed = ids[..num_removed].to_vec();
+    removed.sort();
+
+    let mut puzzle = solution.clone();
+    for &piece in &removed {
+        puzzle.0[piece] = BitCube::default();
+    }
+    (puzzle, removed)
+}
+
+/// Placements of the pieces in the solution after symmetry `nr` of the box
+///
+/// `nr` 0..24 are the rotations of `orient_piece`. With a piece map from `mirror_pieces`,
+/// `nr` 24..48 mirror the solution first, which moves each placement to the mirrored piece.
+pub fn transform_solution(
+    puzzle: &Puzzle,
+    solution: &Solution,
+    nr: usize,
+    mirror: Option<&[usize]>,
+) -> Vec<BitCube> {
+    let transform = cube_symmetries(puzzle)[nr];
+    let mut transformed = vec![BitCube::default(); solution.0.len()];
+    for (piece, placement) in solution.0.iter().enumerate() {
+        let target = if nr < 24 {
+            piece
+        } else {
+            mirror.expect("Mirror symmetry without a piece map")[piece]
+        };
+        transformed[target] = transform.apply(*placement);
+    }
+    transformed
+}
+
+/// Symmetries of the box that solutions are considered equal under, as `nr` for
+/// `transform_solution`
+///
+/// The rotations in `Puzzle::symmetries`, followed by the same rotations after a reflection
+/// if there's a piece map for reflections. All 24 or 48 for a cube.
+pub fn solution_symmetries(puzzle: &Puzzle, mirror: Option<&[usize]>) -> Vec<usize> {
+    let rotations = puzzle.symmetries();
+    if mirror.is_some() {
+        let mirrored = rotations.iter().map(|nr| nr + 24);
+        rotations.iter().copied().chain(mirrored).collect()
+    } else {
+        rotations
+    }
+}
+
+/// Canonical form of a solution, the same for all rotations of the solution
+/// and for all reflections of it if `mirror` is given
+///
+/// This is the lexicographically smallest of the piece placements over all symmetries.
+pub fn canonical_form(
+    puzzle: &Puzzle,
+    solution: &Solution,
+    mirror: Option<&[usize]>,
+) -> Vec<BitCube> {
+    solution_symmetries(puzzle, mirror)
+        .into_iter()
+        .map(|nr| transform_solution(puzzle, solution, nr, mirror))
+        .min()
+        .unwrap()
+}
+
+/// Key of a solution that is the same for all rotations of the solution, and for all
+/// reflections of it if `mirror` is set, and different for all other solutions
+///
+/// Each cell holds the piece that fills it, numbered from 1 in the order the pieces
+/// first appear in the cells and 0 for empty cells. This is the lexicographically
+/// smallest such coloring over all symmetries of the box, so pieces with the same shape
+/// can trade places without changing the key.
+pub fn canonical_key(puzzle: &Puzzle, solution: &Solution, mirror: bool) -> [u8; MAX_CELLS] {
+    solution_colorings(puzzle, solution, mirror).min().unwrap()
+}
+
+/// Number of symmetries of the box that map the solution onto itself, counting the
+/// reflections if `mirror` is set, with pieces of the same shape free to trade places
+///
+/// 1 for most solutions, as only the identity does. Solutions with more are found fewer
+/// times than there are symmetries when searching without symmetry breaking, the number
+/// of symmetries divided by this number of times.
+pub fn stabilizer_size(puzzle: &Puzzle, solution: &Solution, mirror: bool) -> usize {
+    let mut colorings = solution_colorings(puzzle, solution, mirror);
+    // The identity comes first
+    let identity = colorings.next().unwrap();
+    1 + colorings.filter(|coloring| *coloring == identity).count()
+}
+
+/// The solution as a coloring of the cells after each symmetry of the box, the identity
+/// first, see `canonical_key`
+fn solution_colorings(
+    puzzle: &Puzzle,
+    solution: &Solution,
+    mirror: bool,
+) -> impl Iterator<Item = [u8; MAX_CELLS]> {
+    let mut colors = [0u8; MAX_CELLS];
+    for (piece, placement) in solution.0.iter().enumerate() {
+        let mut bits = *placement;
+        while !bits.is_empty() {
+            colors[bits.trailing_zeros() as usize] = piece as u8 + 1;
+            bits &= !bits.lowest_bit();
+        }
+    }
+
+    let num_bits = puzzle.num_bits();
+    let transforms = cube_symmetries(puzzle);
+    let rotations = puzzle.symmetries();
+    // Reflections are the rotations after the reflection, see `cube_symmetries`
+    let reflections: Vec<_> = rotations
+        .iter()
+        .map(|nr| nr + 24)
+        .filter(|_| mirror)
+        .collect();
+    rotations.into_iter().chain(reflections).map(move |nr| {
+        let mut oriented = [0u8; MAX_CELLS];
+        for cell in 0..num_bits {
+            // Symmetries of the box keep every cell in it
+            oriented[transforms[nr].table[cell] as usize] = colors[cell];
+        }
+        let mut labels = [0u8; MAX_PIECES + 1];
+        let mut next_label = 1;
+        for color in &mut oriented[..num_bits] {
+            if *color != 0 {
+                if labels[*color as usize] == 0 {
+                    labels[*color as usize] = next_label;
+                    next_label += 1;
+                }
+                *color = labels[*color as usize];
+            }
+        }
+        oriented
+    })
+}
+
+/// Writes solutions to a stream as they are found, skipping rotations (and reflections if
+/// `mirror` is given) of solutions that have already been written
+///
+/// Writes the same solutions as `filter_unique_solutions` followed by `write_solutions`,
+/// without keeping more than the canonical form of each solution in memory.
+pub struct UniqueWriter<W: std::io::Write> {
+    puzzle: Puzzle,
+    pieces: Vec<Piece>,
+    mirror: Option<Vec<usize>>,
+    seen: std::collections::HashSet<[u8; MAX_CELLS]>,
+    stream: W,
+}
+
+impl<W: std::io::Write> UniqueWriter<W> {
+    pub fn new(puzzle: Puzzle, pieces: Vec<Piece>, mirror: Option<Vec<usize>>, stream: W) -> Self {
+        Self {
+            puzzle,
+            pieces,
+            mirror,
+            seen: std::collections::HashSet::new(),
+            stream,
+        }
+    }
+    /// Number of solutions written so far
+    pub fn num_written(&self) -> usize {
+        self.seen.len()
+    }
+    /// Write the solution unless a rotation of it has been written already
+    /// The stream is flushed after every solution so that the output can be followed live
+    pub fn write(&mut self, solution: &Solution) -> Result<(), std::io::Error> {
+        let key = canonical_key(&self.puzzle, solution, self.mirror.is_some());
+        if !self.seen.insert(key) {
+            return Ok(());
+        }
+        if self.seen.len() == 1 {
+            write_piece_names(&self.pieces, &mut self.stream)?;
+        }
+        writeln!(self.stream, "Solution #{}", self.seen.len() - 1)?;
+        write_solution(&self.puzzle, &self.pieces, solution, &mut self.stream)?;
+        writeln!(self.stream)?;
+        self.stream.flush()
+    }
+}
+
+/// Returns a filtered version of the solutions with only unique solutions
+///
+/// Solutions are the same if one is a rotation of the other, or a reflection if there's
+/// a piece map for reflections from `mirror_pieces`. They are compared by `canonical_key`,
+/// so solutions that only swap pieces of the same shape are the same as well.
+pub fn filter_unique_solutions(
+    puzzle: &Puzzle,
+    solutions: &Vec<Solution>,
+    mirror: Option<&[usize]>,
+) -> Vec<Solution> {
+    let mut unique_solutions = Vec::new();
+
+    // Canonical keys of the solutions kept so far, which tell all distinct solutions apart
+    let mut seen = std::collections::HashSet::new();
+    for solution in solutions {
+        if seen.insert(canonical_key(puzzle, solution, mirror.is_some())) {
+            unique_solutions.push(solution.clone());
+        }
+    }
+    unique_solutions
+}
+
+/// Compare two sets of solutions up to rotation, and reflection if `mirror` is set
+///
+/// Returns the solutions of `a` with no rotation in `b` and those of `b` with no rotation
+/// in `a`, both empty if the sets are the same. The order of the solutions and which
+/// rotation of a solution is in the set don't matter.
+pub fn diff_solutions(
+    puzzle: &Puzzle,
+    a: &[Solution],
+    b: &[Solution],
+    mirror: bool,
+) -> (Vec<Solution>, Vec<Solution>) {
+    let keys = |solutions: &[Solution]| -> std::collections::HashSet<_> {
+        solutions
+            .iter()
+            .map(|solution| canonical_key(puzzle, solution, mirror))
+            .collect()
+    };
+    let (keys_a, keys_b) = (keys(a), keys(b));
+    // Each solution missing from the other set is reported once, not once per rotation
+    let missing = |solutions: &[Solution], other: &std::collections::HashSet<_>| {
+        let mut seen = std::collections::HashSet::new();
+        solutions
+            .iter()
+            .filter(|solution| {
+                let key = canonical_key(puzzle, solution, mirror);
+                !other.contains(&key) && seen.insert(key)
+            })
+            .cloned()
+            .collect()
+    };
+    (missing(a, &keys_b), missing(b, &keys_a))
+}
+
+/// Configuration of a `Solver`, built by chaining the setters on the default one
+///
+/// E.g. `SolverConfig::new().threads(4).heuristic(CellHeuristic::LowestBit).max_solutions(10)`.
+/// The defaults are the standard 4x4x4 cube searched on one thread with the default
+/// options of `SearchOptions` and symmetry breaking, without limits.
+#[derive(Clone)]
+pub struct SolverConfig {
+    dims: [usize; 3],
+    threads: usize,
+    heuristic: CellHeuristic,
+    piece_order: PieceOrder,
+    quick_feasible: bool,
+    prune_regions: bool,
+    forward_checking: bool,
+    symmetry_breaking: bool,
+    max_solutions: Option<usize>,
+    timeout: Option<std::time::Duration>,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SolverConfig {
+    pub fn new() -> Self {
+        Self {
+            dims: [4; 3],
+            threads: 1,
+            heuristic: CellHeuristic::default(),
+            piece_order: PieceOrder::default(),
+            quick_feasible: false,
+            prune_regions: false,
+            forward_checking: false,
+            symmetry_breaking: true,
+            max_solutions: None,
+            timeout: None,
+        }
+    }
+    /// Fill a cube with side length `size`
+    pub fn size(self, size: usize) -> Self {
+        self.box_dims([size; 3])
+    }
+    /// Fill a box with these side lengths, see `Puzzle::new_box`
+    pub fn box_dims(mut self, dims: [usize; 3]) -> Self {
+        self.dims = dims;
+        self
+    }
+    /// Search on this many threads, see `parallel_search`
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+    pub fn heuristic(mut self, heuristic: CellHeuristic) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+    pub fn piece_order(mut self, piece_order: PieceOrder) -> Self {
+        self.piece_order = piece_order;
+        self
+    }
+    /// See `SearchOptions::quick_feasible`
+    pub fn quick_feasible(mut self, quick_feasible: bool) -> Self {
+        self.quick_feasible = quick_feasible;
+        self
+    }
+    /// See `SearchOptions::prune_regions`
+    pub fn prune_regions(mut self, prune_regions: bool) -> Self {
+        self.prune_regions = prune_regions;
+        self
+    }
+    /// See `SearchOptions::forward_checking`
+    pub fn forward_checking(mut self, forward_checking: bool) -> Self {
+        self.forward_checking = forward_checking;
+        self
+    }
+    /// Restrict a piece with `break_symmetry` so that rotated solutions aren't searched
+    pub fn symmetry_breaking(mut self, symmetry_breaking: bool) -> Self {
+        self.symmetry_breaking = symmetry_breaking;
+        self
+    }
+    /// Stop the search once this many solutions have been found
+    pub fn max_solutions(mut self, max_solutions: usize) -> Self {
+        self.max_solutions = Some(max_solutions);
+        self
+    }
+    /// Stop the search once it has run this long
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Puzzle with the configured dimensions for this many pieces
+    pub fn puzzle(&self, num_pieces: usize) -> Puzzle {
+        Puzzle::new_box(self.dims, num_pieces)
+    }
+    /// Options for `search` with this configuration, searching for full solutions in
+    /// every branch
+    pub fn search_options(&self, num_pieces: usize) -> SearchOptions {
+        SearchOptions {
+            max_depth: num_pieces,
+            max_solutions: self.max_solutions,
+            max_unique_solutions: None,
+            quick_feasible: self.quick_feasible,
+            prune_regions: self.prune_regions,
+            forward_checking: self.forward_checking,
+            branches: None,
+            cell_selector: std::sync::Arc::new(self.heuristic),
+            piece_order: self.piece_order,
+            random_seed: None,
+            required: Vec::new(),
+        }
+    }
+    /// Stats for a search with this configuration, with the deadline of the timeout
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::new();
+        stats.quiet = true;
+        stats.deadline = self
+            .timeout
+            .map(|timeout| std::time::Instant::now() + timeout);
+        stats
+    }
+}
+
+/// Finds all unique solutions for a set of pieces
+///
+/// `Solver::new(pieces).solve()` solves the standard 4x4x4 cube with the default search,
+/// `Solver::with_config` takes a `SolverConfig` for the rest of the options. The lower
+/// level `search` gives control over stats and how solutions are kept.
+pub struct Solver {
+    puzzle: Puzzle,
+    pieces: Vec<BitCube>,
+    config: SolverConfig,
+}
+
+impl Solver {
+    /// Solver for filling a 4x4x4 cube with the pieces
+    pub fn new(pieces: Vec<BitCube>) -> Self {
+        Self::with_config(pieces, SolverConfig::new())
+    }
+    /// Solver for filling a cube with side length `size` with the pieces
+    pub fn with_size(size: usize, pieces: Vec<BitCube>) -> Self {
+        Self::with_config(pieces, SolverConfig::new().size(size))
+    }
+    /// Solver for the pieces with the given configuration
+    pub fn with_config(pieces: Vec<BitCube>, config: SolverConfig) -> Self {
+        Self {
+            puzzle: config.puzzle(pieces.len()),
+            pieces,
+            config,
+        }
+    }
+    /// Search the solutions and return the ones that are unique up to rotation
+    ///
+    /// With a timeout or a maximum number of solutions, only the solutions found before
+    /// the search stopped are returned.
+    pub fn solve(&self) -> Vec<Solution> {
+        self.search().0
+    }
+    /// Search the solutions like `solve`, failing with `Error::NoSolution` if the pieces
+    /// can't fill the box
+    ///
+    /// A search that timed out without a solution returns no solutions rather than the
+    /// error, as one may still exist.
+    pub fn try_solve(&self) -> Result<Vec<Solution>, Error> {
+        let (solutions, timed_out) = self.search();
+        if solutions.is_empty() && !timed_out {
+            return Err(Error::NoSolution);
+        }
+        Ok(solutions)
+    }
+    /// Unique solutions and whether the search timed out
+    fn search(&self) -> (Vec<Solution>, bool) {
+        let mut piece_placements = generate_all_placements(&self.puzzle, &self.pieces, &[]);
+        if self.config.symmetry_breaking {
+            break_symmetry(&self.puzzle, &mut piece_placements);
+        }
+        let placements = Placements::new(self.puzzle, piece_placements, true);
+        let options = self.config.search_options(self.puzzle.num_pieces);
+        let mut stats = self.config.stats();
+        let solutions = if self.config.threads > 1 {
+            let first_cell = options
+                .cell_selector
+                .select(BitCube::default(), 0, &placements);
+            let num_branches = (0..self.puzzle.num_pieces)
+                .map(|piece| placements.for_cell(first_cell, piece).count())
+                .sum::<usize>()
+                + usize::from(placements.num_holes > 0);
+            let (solutions, _) = parallel_search(
+                &placements,
+                &options,
+                num_branches,
+                self.config.threads,
+                true,
+                &mut stats,
+            );
+            solutions
+        } else {
+            let mut solutions = Vec::new();
+            let mut picks = Solution(vec![BitCube::default(); self.puzzle.num_pieces]);
+            search(
+                BitCube::default(),
+                0,
+                &placements,
+                &mut picks,
+                &options,
+                &mut stats,
+                &mut |solution| solutions.push(solution.clone()),
+            );
+            solutions
+        };
+        (
+            filter_unique_solutions(&self.puzzle, &solutions, None),
+            stats.timed_out,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the pieces of a piece file for a box with these dimensions
+    fn pieces(dims: [usize; 3], text: &str) -> Vec<Piece> {
+        let puzzle = Puzzle::new_box(dims, 1);
+        read_pieces_from(&puzzle, text.as_bytes(), "test").unwrap()
+    }
+
+    fn bits(pieces: &[Piece]) -> Vec<BitCube> {
+        pieces.iter().map(|piece| piece.bits).collect()
+    }
+
+    #[test]
+    fn unsolvable_pieces_have_no_solution() {
+        // Two bars and an L can't fill a 3x3 square
+        let pieces = pieces(
+            [3, 3, 1],
+            "# 0\n111\n000\n000\n# 1\n111\n000\n000\n# 2\n110\n100\n000\n",
+        );
+        let solver = Solver::with_config(bits(&pieces), SolverConfig::new().box_dims([3, 3, 1]));
+        assert!(matches!(solver.try_solve(), Err(Error::NoSolution)));
+        assert!(solver.solve().is_empty());
+    }
+
+    /// A tripod and a T of the standard cube, both at the origin
+    const TRIPOD_AND_T: &str = "\
+# 0 tripod
+1100
+1000
+0000
+0000
+1000
+0000
+0000
+0000
+# 1 T
+1110
+0100
+0000
+0000
+0000
+0000
+0000
+0000
+";
+
+    #[test]
+    fn written_solution_reads_back() {
+        let puzzle = Puzzle::new(4, 2);
+        let pieces = pieces([4; 3], TRIPOD_AND_T);
+        let solution = Solution(vec![
+            translate(&puzzle, pieces[0].bits, 1, 2, 1),
+            translate(&puzzle, pieces[1].bits, 0, 0, 3),
+        ]);
+        let mut buffer = Vec::new();
+        write_solution(&puzzle, &pieces, &solution, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text.lines().count(), 4);
+
+        // One line per z layer, the rows of y separated by spaces, one character per x,
+        // with the bits laid out as x*16 + y*4 + z
+        let mut read = vec![BitCube::default(); pieces.len()];
+        for (z, line) in text.lines().enumerate() {
+            for (y, row) in line.split_whitespace().enumerate() {
+                for (x, c) in row.chars().enumerate() {
+                    if let Some(p) = pieces.iter().position(|p| p.label() == c.to_string()) {
+                        read[p].set_bit(x * 16 + y * 4 + z);
+                    }
+                }
+            }
+        }
+        for (p, piece) in pieces.iter().enumerate() {
+            assert!(read[p] == solution.0[p], "piece {}: {:x}", p, read[p]);
+            assert!(normalize(&puzzle, read[p]) == piece.bits);
+        }
+        assert!(parse_solution(&puzzle, &text, "test").unwrap() == solution);
+    }
+
+    #[test]
+    fn translate_moves_every_cell() {
+        let puzzle = Puzzle::new(4, 2);
+        let tripod = pieces([4; 3], TRIPOD_AND_T)[0].bits;
+        // (0,0,0), (1,0,0), (0,1,0) and (0,0,1) moved to (1,1,1), (2,1,1), (1,2,1) and (1,1,2)
+        let expected = BitCube(1 << 21 | 1 << 37 | 1 << 25 | 1 << 22);
+        let moved = translate(&puzzle, tripod, 1, 1, 1);
+        assert!(moved == expected, "{:x}", moved);
+        assert!(translate(&puzzle, moved, -1, -1, -1) == tripod);
+    }
+
+    #[test]
+    fn translate_drops_cells_outside_the_box() {
+        let puzzle = Puzzle::new(4, 2);
+        let t = pieces([4; 3], TRIPOD_AND_T)[1].bits;
+        // The T spans x 0..=2, so one cell ends up past x = 3
+        assert_eq!(translate(&puzzle, t, 2, 0, 0).count_ones(), 3);
+        // and the three cells of the bar before y = 0
+        assert_eq!(translate(&puzzle, t, 0, -1, 0).count_ones(), 1);
+        assert!(translate(&puzzle, t, 0, 0, 4).is_empty());
+    }
+
+    #[test]
+    fn copies_of_a_piece_are_congruent() {
+        let puzzle = Puzzle::new(4, 3);
+        // The tripod of TRIPOD_AND_T turned half a turn around z and moved to the
+        // opposite corner
+        let other_corner = "\
+# 2
+0000
+0000
+0001
+0011
+0000
+0000
+0000
+0001
+";
+        let pieces = pieces([4; 3], &format!("{}{}", TRIPOD_AND_T, other_corner));
+        let bits = bits(&pieces);
+        assert!(bits[0] != bits[2]);
+        assert!(canonical_piece(&puzzle, bits[0]) == canonical_piece(&puzzle, bits[2]));
+        assert!(canonical_piece(&puzzle, bits[0]) != canonical_piece(&puzzle, bits[1]));
+        assert_eq!(congruent_pieces(&puzzle, &bits), vec![vec![0, 2]]);
+    }
+
+    /// Cubes that rotations keep all cells of, up to the largest one
+    fn cubes() -> impl Iterator<Item = Puzzle> {
+        (2..=MAX_CUBE_SIZE).map(|size| Puzzle::new(size, 1))
+    }
+
+    /// Corner of three cells, fits every cube
+    fn corner(puzzle: &Puzzle) -> BitCube {
+        pack_cells(puzzle, [(0, 0, 0), (1, 0, 0), (0, 1, 0)])
+    }
+
+    /// Piece without rotational or mirror symmetry, fits cubes of size 3 and up
+    fn chiral(puzzle: &Puzzle) -> BitCube {
+        pack_cells(
+            puzzle,
+            [(0, 0, 0), (1, 0, 0), (2, 0, 0), (0, 1, 0), (1, 0, 1)],
+        )
+    }
+
+    #[test]
+    fn rotation_tables_match_rotating_cells() {
+        for puzzle in cubes() {
+            // Both move each bit on its own, so comparing single bits covers every piece
+            for bit in 0..puzzle.num_bits() {
+                let piece = BitCube(1 << bit);
+                for axis in [Axis::X, Axis::Y, Axis::Z] {
+                    assert!(
+                        rotate_piece_90(&puzzle, piece, axis)
+                            == rotate_piece_90_by_cells(&puzzle, piece, axis),
+                        "size {}, bit {} around {:?}",
+                        puzzle.dims[0],
+                        bit,
+                        axis
+                    );
+                }
+                for nr in 0..24 {
+                    assert!(
+                        orient_piece(&puzzle, piece, nr)
+                            == orient_piece_by_cells(&puzzle, piece, nr),
+                        "size {}, bit {} in orientation {}",
+                        puzzle.dims[0],
+                        bit,
+                        nr
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symmetries_are_distinct_permutations() {
+        for puzzle in cubes() {
+            let symmetries = cube_symmetries(&puzzle);
+            for (nr, transform) in symmetries.iter().enumerate() {
+                assert!(
+                    transform.is_permutation(puzzle.num_bits()),
+                    "symmetry {}",
+                    nr
+                );
+                assert!(!symmetries[..nr].contains(transform), "symmetry {}", nr);
+            }
+        }
+    }
+
+    #[test]
+    fn four_rotations_give_the_original() {
+        for puzzle in cubes().skip(1) {
+            for piece in [corner(&puzzle), chiral(&puzzle)] {
+                for axis in [Axis::X, Axis::Y, Axis::Z] {
+                    let mut rotated = piece;
+                    for turn in 1..=4 {
+                        rotated = rotate_piece_90(&puzzle, rotated, axis);
+                        assert_eq!(rotated.count_ones(), piece.count_ones());
+                        assert_eq!(
+                            rotated == piece,
+                            turn == 4,
+                            "turn {} around {:?}",
+                            turn,
+                            axis
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_around_z_moves_the_corner() {
+        // Around Z, (x, y) takes the cells from (3 - y, x), so (x, y) moves to (y, 3 - x)
+        let puzzle = Puzzle::new(4, 1);
+        let expected = pack_cells(&puzzle, [(0, 3, 0), (0, 2, 0), (1, 3, 0)]);
+        assert!(rotate_piece_90(&puzzle, corner(&puzzle), Axis::Z) == expected);
+    }
+
+    #[test]
+    fn chiral_piece_has_24_orientations() {
+        for puzzle in cubes().skip(1) {
+            let chiral = chiral(&puzzle);
+            // All compositions of rotations around the three axes
+            let mut rotations = std::collections::BTreeSet::from([chiral]);
+            let mut queue = vec![chiral];
+            while let Some(piece) = queue.pop() {
+                for axis in [Axis::X, Axis::Y, Axis::Z] {
+                    let rotated = rotate_piece_90(&puzzle, piece, axis);
+                    if rotations.insert(rotated) {
+                        queue.push(rotated);
+                    }
+                }
+            }
+            let oriented: std::collections::BTreeSet<_> = (0..24)
+                .map(|nr| orient_piece(&puzzle, chiral, nr))
+                .collect();
+            assert_eq!(rotations.len(), 24);
+            assert!(rotations == oriented);
+            assert_eq!(orientations(&puzzle, chiral).len(), 24);
+
+            let mirrored = normalize(&puzzle, mirror_piece(&puzzle, chiral));
+            assert!(!rotations
+                .iter()
+                .any(|piece| normalize(&puzzle, *piece) == mirrored));
+        }
+    }
+
+    /// Hash that unique solutions used to be filtered on, the placements shifted by their
+    /// piece and XORed
+    fn xor_hash(solution: &Solution) -> u64 {
+        solution
+            .0
+            .iter()
+            .enumerate()
+            .fold(0, |hash, (p, placement)| hash ^ (placement.0 as u64) << p)
+    }
+
+    #[test]
+    fn solutions_with_colliding_hashes_are_kept() {
+        let puzzle = Puzzle::new(4, 2);
+        let cells = |bits: &[usize]| {
+            bits.iter()
+                .map(|bit| BitCube(1 << bit))
+                .fold(BitCube::default(), |a, b| a | b)
+        };
+        // A domino and a cube in a straight line, and bent into an L
+        let line = Solution(vec![cells(&[1, 2]), cells(&[0])]);
+        let bent = Solution(vec![cells(&[2, 6]), cells(&[5])]);
+        assert_eq!(xor_hash(&line), xor_hash(&bent));
+        assert_eq!(
+            filter_unique_solutions(&puzzle, &vec![line, bent], None).len(),
+            2
+        );
+    }
+
+    /// The seven Soma pieces, which fill a 3x3x3 cube in 240 ways up to rotation and
+    /// reflection
+    const SOMA: &str = "\
+# 0 V
+110
+100
+000
+# 1 L
+111
+100
+000
+# 2 T
+111
+010
+000
+# 3 S
+110
+011
+000
+# 4 A
+110
+010
+000
+000
+010
+000
+# 5 B
+110
+010
+000
+010
+000
+000
+# 6 P
+110
+010
+000
+100
+000
+000
+";
+
+    /// Placements of the Soma pieces in the 3x3x3 cube, with one piece restricted by
+    /// `break_symmetry`
+    fn soma_placements() -> Placements {
+        let pieces = pieces([3; 3], SOMA);
+        let puzzle = Puzzle::new(3, pieces.len());
+        let mut piece_placements = all_placements(&puzzle, &pieces);
+        break_symmetry(&puzzle, &mut piece_placements);
+        Placements::new(puzzle, piece_placements, true)
+    }
+
+    /// All solutions found by `search` with the default options
+    fn search_all(placements: &Placements) -> Vec<Solution> {
+        let num_pieces = placements.puzzle.num_pieces;
+        let config = SolverConfig::new();
+        let mut picks = Solution(vec![BitCube::default(); num_pieces]);
+        let mut solutions = Vec::new();
+        search(
+            BitCube::default(),
+            0,
+            placements,
+            &mut picks,
+            &config.search_options(num_pieces),
+            &mut config.stats(),
+            &mut |solution| solutions.push(solution.clone()),
+        );
+        solutions
+    }
+
+    #[test]
+    fn dlx_finds_the_same_solutions() {
+        let placements = soma_placements();
+        let puzzle = placements.puzzle;
+        let config = SolverConfig::new();
+        let mut picks = Solution(vec![BitCube::default(); puzzle.num_pieces]);
+        let mut dlx = Vec::new();
+        Dlx::new(&placements).search(
+            &puzzle,
+            &mut picks,
+            &config.search_options(puzzle.num_pieces),
+            &mut config.stats(),
+            &mut |solution| dlx.push(solution.clone()),
+        );
+        let backtracking = search_all(&placements);
+
+        let dlx = filter_unique_solutions(&puzzle, &dlx, None);
+        let backtracking = filter_unique_solutions(&puzzle, &backtracking, None);
+        assert_eq!(dlx.len(), 480);
+        assert_eq!(backtracking.len(), 480);
+        let (only_dlx, only_backtracking) = diff_solutions(&puzzle, &dlx, &backtracking, false);
+        assert!(only_dlx.is_empty() && only_backtracking.is_empty());
+    }
+
+    #[test]
+    fn symmetry_breaking_keeps_the_unique_solutions() {
+        let placements = soma_placements();
+        let puzzle = placements.puzzle;
+        let broken = search_all(&placements);
+
+        let pieces = pieces([3; 3], SOMA);
+        let unbroken = search_all(&Placements::new(
+            puzzle,
+            all_placements(&puzzle, &pieces),
+            true,
+        ));
+        assert_eq!(unbroken.len(), 24 * broken.len());
+
+        let broken = filter_unique_solutions(&puzzle, &broken, None);
+        let unbroken = filter_unique_solutions(&puzzle, &unbroken, None);
+        assert_eq!(broken.len(), 480);
+        assert_eq!(unbroken.len(), 480);
+        let (only_broken, only_unbroken) = diff_solutions(&puzzle, &broken, &unbroken, false);
+        assert!(only_broken.is_empty() && only_unbroken.is_empty());
+    }
+
+    /// Three pieces that fill a 2x2x3 box, each 3 layers deep
+    const TALL: &str = "\
+# 0 tall
+10
+00
+10
+00
+10
+00
+# 1
+01
+01
+01
+01
+01
+01
+# 2
+00
+10
+00
+10
+00
+10
+";
+
+    /// Message of the error reading a piece file for a box with these dimensions
+    fn parse_error(dims: [usize; 3], text: &str) -> String {
+        let puzzle = Puzzle::new_box(dims, 1);
+        match read_pieces_from(&puzzle, text.as_bytes(), "test") {
+            Ok(pieces) => panic!("read {} pieces, expected an error", pieces.len()),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_skipped() {
+        let text = "\
+# The tall set
+
+# 0 tall   \n\
+10
+00  \n\n\
+10
+# middle layer
+00
+10
+00
+# 1
+01
+01
+01
+01
+01
+01
+
+# 2
+00
+10
+00
+10
+00
+10
+
+";
+        let expected = pieces([2, 2, 3], TALL);
+        let read = pieces([2, 2, 3], text);
+        assert!(bits(&read) == bits(&expected));
+        assert_eq!(read[0].name.as_deref(), Some("tall"));
+    }
+
+    #[test]
+    fn short_row_is_an_error() {
+        let text = "# 0\n10\n0\n10\n00\n10\n00\n";
+        assert_eq!(
+            parse_error([2, 2, 3], text),
+            "test:3:2: piece 0: expected 2 cells in a row"
+        );
+    }
+
+    #[test]
+    fn unexpected_character_is_an_error() {
+        // Line numbers count the lines of the file, blank lines and comments included
+        let text = format!("{}\n# a comment\n# 3\n10\n0x\n", TALL);
+        assert_eq!(
+            parse_error([2, 2, 3], &text),
+            "test:26:2: piece 3: unexpected 'x', expected '0' or '1'"
+        );
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        let expected = "test: no pieces, expected a piece header like \"# 0\"";
+        assert_eq!(parse_error([2, 2, 3], ""), expected);
+        assert_eq!(parse_error([2, 2, 3], "\n  \n\n"), expected);
+        assert_eq!(parse_error([2, 2, 3], "# only a comment\n"), expected);
+    }
+
+    #[test]
+    fn truncated_piece_is_an_error() {
+        // The last piece stops in the middle of its second layer
+        let text = format!("{}# 3\n10\n00\n10\n", TALL);
+        assert_eq!(
+            parse_error([2, 2, 3], &text),
+            "test:26:1: piece 3: truncated, expected another row, the file ends after 3 complete pieces"
+        );
+        assert_eq!(
+            parse_error([2, 2, 3], "# 0\n"),
+            "test:2:1: piece 0: truncated, expected another row, the file ends after 0 complete pieces"
+        );
+    }
+
+    #[test]
+    fn grids_with_other_characters() {
+        let puzzle = Puzzle::new_box([2, 2, 3], 1);
+        let chars: GridChars = "#.".parse().unwrap();
+        // Rows starting with '#' are rows rather than comments
+        let text = "\
+# 0 tall
+#.
+..
+#.
+..
+#.
+..
+# 1
+.#
+.#
+.#
+.#
+.#
+.#
+# 2
+..
+#.
+..
+#.
+..
+#.
+";
+        let read = read_puzzles_from(&puzzle, text.as_bytes(), "test", chars).unwrap();
+        assert_eq!(read.len(), 1);
+        assert!(bits(&read[0].1) == bits(&pieces([2, 2, 3], TALL)));
+
+        // The default characters are errors in a grid of other characters
+        let error = read_puzzles_from(&puzzle, TALL.as_bytes(), "test", chars).err();
+        assert_eq!(
+            error.map(|err| err.to_string()).as_deref(),
+            Some("test:2:1: piece 0: unexpected '1', expected '.' or '#'")
+        );
+    }
+
+    #[test]
+    fn pieces_of_three_layers() {
+        let puzzle = Puzzle::new(4, 3);
+        // A staircase over three layers after pieces of the usual two
+        let text = format!(
+            "{}# 2 stairs\n1100\n0000\n0000\n0000\n0110\n0000\n0000\n0000\n0011\n0000\n0000\n0000\n",
+            TRIPOD_AND_T
+        );
+        let pieces = pieces([4; 3], &text);
+        assert_eq!(pieces.len(), 3);
+        let stairs = pack_cells(
+            &puzzle,
+            [
+                (0, 0, 0),
+                (1, 0, 0),
+                (1, 0, 1),
+                (2, 0, 1),
+                (2, 0, 2),
+                (3, 0, 2),
+            ],
+        );
+        assert!(pieces[2].bits == stairs);
+        let (min, max) = bounding_box(&puzzle, pieces[2].bits);
+        assert_eq!((min.2, max.2), (0, 2));
+    }
+
+    /// A solution of the Soma cube and its pieces
+    fn soma_solution() -> (Puzzle, Vec<Piece>, Solution) {
+        let pieces = pieces([3; 3], SOMA);
+        let puzzle = Puzzle::new(3, pieces.len());
+        let solver =
+            Solver::with_config(bits(&pieces), SolverConfig::new().size(3).max_solutions(1));
+        let solution = solver.solve().swap_remove(0);
+        (puzzle, pieces, solution)
+    }
+
+    #[test]
+    fn overlapping_pieces_are_invalid() {
+        let (puzzle, pieces, solution) = soma_solution();
+        assert!(validate_solution(&puzzle, &solution));
+        assert!(verify_solution(&puzzle, &pieces, &solution).is_empty());
+
+        // Any other placement of a piece overlaps the others in the full cube
+        let mut overlapping = solution.clone();
+        overlapping.0[0] = *pieces[0]
+            .placements(&puzzle)
+            .iter()
+            .find(|placement| **placement != solution.0[0])
+            .unwrap();
+        assert!(!validate_solution(&puzzle, &overlapping));
+        assert_eq!(
+            verify_solution(&puzzle, &pieces, &overlapping),
+            vec!["The pieces overlap or leave the box"]
+        );
+    }
+
+    #[test]
+    fn missing_cell_is_invalid() {
+        let (puzzle, pieces, solution) = soma_solution();
+        // `validate_solution` doesn't know the pieces, so a piece that lost a cell is left
+        // to `verify_solution`
+        let mut missing = solution.clone();
+        missing.0[3] = missing.0[3] & !missing.0[3].lowest_bit();
+        assert_eq!(
+            verify_solution(&puzzle, &pieces, &missing),
+            vec![
+                "The 3 cells of piece D aren't a placement of it",
+                "1 cells are empty that the pieces should fill"
+            ]
+        );
+
+        // Without a piece
+        let mut unplaced = solution.clone();
+        unplaced.0[3] = BitCube::default();
+        assert!(!validate_solution(&puzzle, &unplaced));
+    }
+
+    #[test]
+    fn binary_solutions_read_back() {
+        let placements = soma_placements();
+        let puzzle = placements.puzzle;
+        let solutions = search_all(&placements);
+        let path = std::env::temp_dir().join(format!("bedlam-test-{}.bin", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        write_solutions_bin(&puzzle, &solutions, &mut file).unwrap();
+        drop(file);
+
+        let read = read_solutions_bin(&puzzle, &path);
+        // Only for the box and the number of pieces it was written for
+        let other = read_solutions_bin(&Puzzle::new(3, 6), &path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read.unwrap() == solutions);
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn json_and_text_pieces_are_the_same() {
+        let puzzle = Puzzle::new(3, 1);
+        let json = r#"[
+            {"name": "V", "cells": [[0, 0, 0], [1, 0, 0], [0, 1, 0]]},
+            {"name": "L", "cells": [[0, 0, 0], [1, 0, 0], [2, 0, 0], [0, 1, 0]]},
+            {"name": "T", "cells": [[0, 0, 0], [1, 0, 0], [2, 0, 0], [1, 1, 0]]},
+            {"name": "S", "cells": [[0, 0, 0], [1, 0, 0], [1, 1, 0], [2, 1, 0]]},
+            {"name": "A", "cells": [[0, 0, 0], [1, 0, 0], [1, 1, 0], [1, 1, 1]]},
+            {"name": "B", "cells": [[0, 0, 0], [1, 0, 0], [1, 1, 0], [1, 0, 1]]},
+            [[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 0, 1]]
+        ]"#;
+        let from_json = read_pieces_json_from(&puzzle, json.as_bytes(), "test.json").unwrap();
+        let from_text = pieces([3; 3], SOMA);
+        assert!(bits(&from_json) == bits(&from_text));
+        let names =
+            |pieces: &[Piece]| -> Vec<_> { pieces.iter().map(|p| p.name.clone()).collect() };
+        assert_eq!(names(&from_json[..6]), names(&from_text[..6]));
+        assert_eq!(from_json[6].name, None);
+    }
+
+    #[test]
+    fn pieces_from_a_reader() {
+        let puzzle = Puzzle::new(4, 1);
+        let text = include_str!("../pieces.txt");
+        let from_reader = read_pieces_from(&puzzle, text.as_bytes(), "-").unwrap();
+        let from_file =
+            read_pieces(&puzzle, concat!(env!("CARGO_MANIFEST_DIR"), "/pieces.txt")).unwrap();
+        assert_eq!(from_reader.len(), 13);
+        assert!(bits(&from_reader) == bits(&from_file));
+    }
+
+    #[test]
+    fn signatures_tell_rotations_apart() {
+        let (puzzle, _, solution) = soma_solution();
+        let signature = solution_signature(&puzzle, &solution);
+        assert_eq!(signature.len(), 27);
+        assert_eq!(solution_signature(&puzzle, &solution.clone()), signature);
+
+        let rotations: std::collections::HashSet<_> = (0..24)
+            .map(|nr| Solution(transform_solution(&puzzle, &solution, nr, None)))
+            .map(|rotated| solution_signature(&puzzle, &rotated))
+            .collect();
+        assert_eq!(rotations.len(), 24);
+        assert!(rotations.contains(&signature));
+    }
+
+    #[test]
+    fn canonical_keys_of_rotations_are_the_same() {
+        let (puzzle, _, solution) = soma_solution();
+        let key = canonical_key(&puzzle, &solution, false);
+        for nr in 0..24 {
+            let rotated = Solution(transform_solution(&puzzle, &solution, nr, None));
+            assert!(
+                canonical_key(&puzzle, &rotated, false) == key,
+                "rotation {}",
+                nr
+            );
+        }
+
+        // The mirror image is only the same with mirror images counted as the same
+        let mirror = mirror_pieces(&puzzle, &bits(&pieces([3; 3], SOMA))).unwrap();
+        let mirrored = Solution(transform_solution(&puzzle, &solution, 24, Some(&mirror)));
+        assert!(canonical_key(&puzzle, &mirrored, false) != key);
+        assert!(canonical_key(&puzzle, &mirrored, true) == canonical_key(&puzzle, &solution, true));
+
+        let other = Solver::with_config(bits(&pieces([3; 3], SOMA)), SolverConfig::new().size(3))
+            .solve()
+            .into_iter()
+            .find(|other| *other != solution)
+            .unwrap();
+        assert!(canonical_key(&puzzle, &other, false) != key);
+    }
+
+    #[test]
+    fn canonical_keys_ignore_which_congruent_piece_goes_where() {
+        // The first and last of the tall pieces have the same shape
+        let pieces = pieces([2, 2, 3], TALL);
+        let puzzle = Puzzle::new_box([2, 2, 3], pieces.len());
+        let solution = Solution(bits(&pieces));
+        let swapped = Solution(vec![pieces[2].bits, pieces[1].bits, pieces[0].bits]);
+        assert!(
+            canonical_key(&puzzle, &swapped, false) == canonical_key(&puzzle, &solution, false)
+        );
+        assert_eq!(
+            filter_unique_solutions(&puzzle, &vec![solution, swapped], None).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn coloring_rules_out_the_tetrominoes() {
+        // The T covers 3 cells of one color and 1 of the other on a checkerboard, the
+        // other tetrominoes 2 of each, so they can't cover the 10 cells of each color
+        let text = "\
+# 0 I
+1111
+0000
+0000
+0000
+0000
+# 1 O
+1100
+1100
+0000
+0000
+0000
+# 2 T
+1110
+0100
+0000
+0000
+0000
+# 3 S
+1100
+0110
+0000
+0000
+0000
+# 4 L
+1110
+1000
+0000
+0000
+0000
+";
+        let pieces = pieces([4, 5, 1], text);
+        let puzzle = Puzzle::new_box([4, 5, 1], pieces.len());
+        assert!(!coloring_feasible(
+            &puzzle,
+            &all_placements(&puzzle, &pieces),
+            2
+        ));
+
+        let placements = soma_placements();
+        assert!(coloring_feasible(
+            &placements.puzzle,
+            &placements.piece_placements,
+            2
+        ));
+        assert!(coloring_feasible(
+            &placements.puzzle,
+            &placements.piece_placements,
+            3
+        ));
+    }
+
+    #[test]
+    fn regions_of_a_box_of_128_cells() {
+        // Two 4x4x4 cubes fill the 4x4x8 box, 128 cells in all
+        let puzzle = Puzzle::new_box([4, 4, 8], 2);
+        let half = pack_cells(
+            &puzzle,
+            (0..4).flat_map(|x| (0..4).flat_map(move |y| (0..4).map(move |z| (x, y, z)))),
+        );
+        let both = vec![half, translate(&puzzle, half, 0, 0, 4)];
+        let placements = Placements::new(puzzle, vec![both.clone(), both], true);
+        assert!(regions_feasible(BitCube::default(), 0, &placements));
+        assert!(quick_feasible(BitCube::default(), 0, &placements));
+        assert!(regions_feasible(half, 1, &placements));
+        // A region of 63 cells is left after a cell of the second half is filled
+        assert!(!regions_feasible(half | BitCube(1 << 127), 1, &placements));
+    }
+
+    /// Whether every tag of the document is closed in order and every '&' starts an entity,
+    /// enough of XML to catch unescaped text
+    fn well_formed(xml: &str) -> bool {
+        let mut open = Vec::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            let Some(end) = rest[start..].find('>') else {
+                return false;
+            };
+            let tag = &rest[start + 1..start + end];
+            let text = &rest[..start];
+            rest = &rest[start + end + 1..];
+            let entities = ["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"];
+            let ampersands_escaped = |text: &str| {
+                text.match_indices('&')
+                    .all(|(i, _)| entities.iter().any(|e| text[i..].starts_with(e)))
+            };
+            if text.contains('>') || !ampersands_escaped(text) || !ampersands_escaped(tag) {
+                return false;
+            }
+            if tag.starts_with('?') {
+                continue;
+            }
+            // Attribute values have to be quoted and free of '<'
+            if tag.contains('<') || !tag.matches('"').count().is_multiple_of(2) {
+                return false;
+            }
+            let name = tag
+                .trim_start_matches('/')
+                .split([' ', '/'])
+                .next()
+                .unwrap();
+            if tag.starts_with('/') {
+                if open.pop() != Some(name) {
+                    return false;
+                }
+            } else if !tag.ends_with('/') {
+                open.push(name);
+            }
+        }
+        open.is_empty() && !rest.contains('>')
+    }
+
+    #[test]
+    fn burr_tools_file_is_well_formed() {
+        let (puzzle, mut pieces, solution) = soma_solution();
+        pieces[0].name = Some("<V> & \"vee\"".to_string());
+        pieces[1].name = Some("Lenny's L".to_string());
+        // The second solution has the V where the L should be
+        let mut broken = solution.clone();
+        broken.0[1] = broken.0[0];
+        let solutions = [solution, broken];
+
+        let mut buffer = Vec::new();
+        write_burr_tools(&puzzle, &pieces, &solutions[..1], &mut buffer).unwrap();
+        let xml = String::from_utf8(buffer).unwrap();
+        assert!(xml.contains(" name=\"&lt;V&gt; &amp; &quot;vee&quot;\""));
+        assert!(xml.contains(" name=\"Lenny&apos;s L\""));
+        assert!(well_formed(&xml), "{}", xml);
+        assert!(!well_formed("<a><b></a></b>"));
+        assert!(!well_formed("<a name=\"x & y\"/>"));
+
+        let err = write_burr_tools(&puzzle, &pieces, &solutions, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "Solution #1: the placement of piece Lenny's L is not a rotation of it"
+        );
+    }
+
+    #[test]
+    fn burr_tools_placements() {
+        // The L of the Soma pieces turned once around z, with its long side along y
+        let puzzle = Puzzle::new(3, 1);
+        let l = pieces([3; 3], SOMA)[1].bits;
+        let turned = pack_cells(&puzzle, [(1, 0, 0), (1, 1, 0), (1, 2, 0), (0, 0, 0)]);
+        // Its hotspot, the corner next to the foot, turns to (1, 0, 0)
+        let (transform, Coords(x, y, z)) = burr_tools_placement(&puzzle, l, turned).unwrap();
+        assert_eq!((transform, x, y, z), (16, 1, 0, 0));
+        let unturned = translate(&puzzle, l, 0, 1, 2);
+        let (transform, Coords(x, y, z)) = burr_tools_placement(&puzzle, l, unturned).unwrap();
+        assert_eq!((transform, x, y, z), (0, 0, 1, 2));
+
+        // Turning the shapes of the pieces by the transforms of an assembly and moving their
+        // hotspots to the positions gives back the solution
+        let (puzzle, pieces, solution) = soma_solution();
+        for (piece, placement) in pieces.iter().zip(&solution.0) {
+            let (t, Coords(x, y, z)) =
+                burr_tools_placement(&puzzle, piece.bits, *placement).unwrap();
+            let (Coords(x0, y0, z0), _) = bounding_box(&puzzle, piece.bits);
+            let cells = cells(&puzzle, piece.bits).into_iter().map(|(cx, cy, cz)| {
+                let cell = ((cx - x0) as i32, (cy - y0) as i32, (cz - z0) as i32);
+                let (tx, ty, tz) = burr_tools_turn(cell, t);
+                (
+                    (tx + x as i32) as usize,
+                    (ty + y as i32) as usize,
+                    (tz + z as i32) as usize,
+                )
+            });
+            assert!(pack_cells(&puzzle, cells) == *placement);
+        }
+    }
+
+    #[test]
+    fn pieces_with_the_same_placements_are_told_apart() {
+        // Two dominoes fill the 2x2 square, either one can go in each placement
+        let pieces = pieces([2, 2, 1], "# 0\n11\n00\n# 1\n11\n00\n");
+        let puzzle = Puzzle::new_box([2, 2, 1], pieces.len());
+        let piece_placements = all_placements(&puzzle, &pieces);
+        let shared = shared_placements(&piece_placements);
+        assert_eq!(shared.len(), 4);
+        assert!(shared.iter().all(|(_, pieces)| *pieces == vec![0, 1]));
+
+        let (placement, _) = shared[0];
+        let solutions = search_all(&Placements::new(puzzle, piece_placements, true));
+        assert_eq!(solutions.len(), 4);
+        for piece in [0, 1] {
+            assert!(
+                solutions
+                    .iter()
+                    .any(|solution| solution.0[piece] == placement),
+                "piece {}",
+                piece
+            );
+        }
+    }
+
+    #[test]
+    fn merged_shards_are_the_unsharded_solutions() {
+        use std::io::Write;
+
+        let placements = soma_placements();
+        let puzzle = placements.puzzle;
+        let pieces = pieces([3; 3], SOMA);
+        let config = SolverConfig::new();
+        let options = config.search_options(puzzle.num_pieces);
+        let first_cell = options
+            .cell_selector
+            .select(BitCube::default(), 0, &placements);
+        let num_branches: usize = (0..puzzle.num_pieces)
+            .map(|piece| placements.for_cell(first_cell, piece).count())
+            .sum();
+
+        // Each shard searches its range of the top level branches, written like --shard-output
+        let dir = std::env::temp_dir().join(format!("bedlam-test-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let num_shards = 3;
+        for shard in 0..num_shards {
+            let branches =
+                shard * num_branches / num_shards..(shard + 1) * num_branches / num_shards;
+            let options = SearchOptions {
+                branches: Some(branches.clone()),
+                ..options.clone()
+            };
+            let mut picks = Solution(vec![BitCube::default(); puzzle.num_pieces]);
+            let mut solutions = Vec::new();
+            search(
+                BitCube::default(),
+                0,
+                &placements,
+                &mut picks,
+                &options,
+                &mut config.stats(),
+                &mut |solution| solutions.push(solution.clone()),
+            );
+            let path = dir.join(format!("shard-{}-of-{}.txt", shard, num_shards));
+            let mut file = std::fs::File::create(path).unwrap();
+            writeln!(
+                file,
+                "# Shard {}/{}, branches {}..{} of {}",
+                shard, num_shards, branches.start, branches.end, num_branches
+            )
+            .unwrap();
+            write_solutions(&puzzle, &pieces, &solutions, &mut file).unwrap();
+        }
+        let merged = merge_shards(&puzzle, dir.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let merged = merged.unwrap();
+        let unsharded = search_all(&placements);
+        assert!(merged == unsharded);
+        let (only_merged, only_unsharded) = diff_solutions(&puzzle, &merged, &unsharded, false);
+        assert!(only_merged.is_empty() && only_unsharded.is_empty());
+    }
+
+    #[test]
+    fn solutions_are_written_the_same_in_every_run() {
+        let pieces = pieces([3; 3], SOMA);
+        let puzzle = Puzzle::new(3, pieces.len());
+        // The placements are collected in a HashSet, which iterates in a different order
+        // in each run
+        let run = || {
+            let solutions = Solver::with_config(bits(&pieces), SolverConfig::new().size(3)).solve();
+            let mut buffer = Vec::new();
+            write_solutions(&puzzle, &pieces, &solutions, &mut buffer).unwrap();
+            buffer
+        };
+        let first = run();
+        assert!(!first.is_empty());
+        assert!(run() == first);
+    }
+
+    #[test]
+    fn labels_of_30_pieces_line_up() {
+        // One cell per piece, piece p in the cell of bit p, x*5 + y
+        let puzzle = Puzzle::new_box([6, 5, 1], 30);
+        let pieces: Vec<_> = (0..30)
+            .map(|id| Piece {
+                id,
+                name: None,
+                bits: BitCube(1 << id),
+                fixed: false,
+            })
+            .collect();
+        let solution = Solution(bits(&pieces));
+        assert_eq!(label_width(30), 2);
+        assert_eq!(
+            [25, 26, 27, 29].map(piece_label),
+            ["Z", "AA", "AB", "AD"].map(String::from)
+        );
+
+        let mut buffer = Vec::new();
+        write_solution(&puzzle, &pieces, &solution, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        // Labels padded to 2 characters and separated by a space, so every cell of a row
+        // starts 3 characters after the one before, and rows of 17 characters are
+        // separated by 4 spaces
+        assert_eq!(
+            text,
+            "A  F  K  P  U  Z     B  G  L  Q  V  AA    C  H  M  R  W  AB    \
+             D  I  N  S  X  AC    E  J  O  T  Y  AD\n"
+        );
+        let line = text.trim_end();
+        for y in 0..5 {
+            for x in 0..6 {
+                let start = 21 * y + 3 * x;
+                let cell = &line[start..(start + 2).min(line.len())];
+                assert_eq!(cell.trim_end(), piece_label(x * 5 + y), "({}, {})", x, y);
+            }
+        }
+        assert!(parse_solution(&puzzle, &text, "test").unwrap() == solution);
+    }
+
+    #[test]
+    fn fitting_placements_are_disjoint_from_the_state() {
+        let placements = soma_placements();
+        let puzzle = placements.puzzle;
+        // The L in the corner of the cube, leaving the cell inside its bend empty
+        let l = pieces([3; 3], SOMA)[1].bits;
+        let (state, used_pieces) = (l, 1 << 1);
+        let cell = puzzle.bit_index(1, 1, 0);
+        assert!(!state.test_bit(cell));
+
+        let fitting: Vec<_> = placements.fitting(cell, state, used_pieces).collect();
+        assert!(!fitting.is_empty());
+        for (piece, placement) in &fitting {
+            assert_ne!(*piece, 1);
+            assert!(placement.is_disjoint(state) && placement.test_bit(cell));
+        }
+        // Every such placement of the other pieces, in the order of the pieces
+        let expected: Vec<_> = (0..puzzle.num_pieces)
+            .filter(|piece| *piece != 1)
+            .flat_map(|piece| {
+                placements.piece_placements[piece]
+                    .iter()
+                    .filter(|p| p.is_disjoint(state) && p.test_bit(cell))
+                    .map(move |p| (piece, *p))
+            })
+            .collect();
+        assert!(fitting == expected);
+
+        let all_used = (1 << puzzle.num_pieces) - 1;
+        assert_eq!(placements.fitting(cell, state, all_used).count(), 0);
+    }
+
+    #[test]
+    fn puzzles_of_one_file_solve_on_their_own() {
+        let puzzle = Puzzle::new_box([2, 2, 3], 1);
+        // Two slabs fill the box as well, each a puzzle of its own next to the tall pieces
+        let text = format!(
+            "# Two sets for the 2x2x3 box\n=== tall ===\n{}=== slabs ===\n\
+             # 0\n11\n00\n11\n00\n11\n00\n# 1\n11\n00\n11\n00\n11\n00\n",
+            TALL
+        );
+        let puzzles =
+            read_puzzles_from(&puzzle, text.as_bytes(), "test", GridChars::default()).unwrap();
+        let names: Vec<_> = puzzles.iter().map(|(name, _)| name.as_deref()).collect();
+        assert_eq!(names, [Some("tall"), Some("slabs")]);
+        assert_eq!(puzzles[0].1.len(), 3);
+        assert_eq!(puzzles[1].1.len(), 2);
+        assert_eq!(puzzles[1].1[0].id, 0);
+
+        for (name, pieces) in &puzzles {
+            let config = SolverConfig::new().box_dims([2, 2, 3]);
+            let solutions = Solver::with_config(bits(pieces), config).try_solve();
+            let solutions = solutions.unwrap_or_else(|err| panic!("{:?}: {}", name, err));
+            let puzzle = Puzzle::new_box([2, 2, 3], pieces.len());
+            for solution in &solutions {
+                assert!(verify_solution(&puzzle, pieces, solution).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_solutions_have_larger_stabilizers() {
+        // The tall pieces as given: a slab at x = 1 and the two congruent columns next to it,
+        // which trade places when the box is turned half a turn around x or mirrored in y
+        let pieces = pieces([2, 2, 3], TALL);
+        let puzzle = Puzzle::new_box([2, 2, 3], pieces.len());
+        let solution = Solution(bits(&pieces));
+        assert_eq!(stabilizer_size(&puzzle, &solution, false), 2);
+        assert_eq!(stabilizer_size(&puzzle, &solution, true), 4);
+
+        let (puzzle, _, solution) = soma_solution();
+        assert_eq!(stabilizer_size(&puzzle, &solution, false), 1);
+        assert_eq!(stabilizer_size(&puzzle, &solution, true), 1);
+    }
+
+    #[test]
+    fn placements_of_a_chiral_piece_have_no_mirror_images() {
+        for puzzle in cubes().skip(1) {
+            let chiral = chiral(&puzzle);
+            let placements = generate_placements(&puzzle, chiral, false, false);
+            let shapes: std::collections::HashSet<_> = placements
+                .iter()
+                .map(|placement| normalize(&puzzle, *placement))
+                .collect();
+            for placement in &placements {
+                let mirrored = normalize(&puzzle, mirror_piece(&puzzle, *placement));
+                assert!(!shapes.contains(&mirrored), "{:x}", placement);
+            }
+
+            // With mirrors, every placement of the mirror image is added to them
+            let with_mirrors = generate_placements(&puzzle, chiral, false, true);
+            assert_eq!(with_mirrors.len(), 2 * placements.len());
+        }
+    }
+
+    #[test]
+    fn pieces_built_with_set() {
+        let puzzle = Puzzle::new(4, 1);
+        // The T of TRIPOD_AND_T, (0,0,0), (1,0,0), (2,0,0) and (1,1,0), at bits x*16 + y*4 + z,
+        // and a cell in the far corner that is cleared again
+        let mut t = BitCube::default();
+        for (x, y, z) in [(0, 0, 0), (1, 0, 0), (2, 0, 0), (1, 1, 0), (3, 3, 3)] {
+            t.set(&puzzle, Coords(x, y, z), true);
+        }
+        t.set(&puzzle, Coords(3, 3, 3), false);
+        assert!(
+            t == BitCube(1 << 0 | 1 << 16 | 1 << 32 | 1 << 20),
+            "{:x}",
+            t
+        );
+        assert!(t == pieces([4; 3], TRIPOD_AND_T)[1].bits);
+        assert!(t.index(&puzzle, Coords(1, 1, 0)) && !t.index(&puzzle, Coords(3, 3, 3)));
+    }
+}
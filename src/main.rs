@@ -1,434 +1,1860 @@
-use std::hash::Hash;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 
-const CUBE_SIZE: usize = 4;
-const CUBE_NUM_BITS: usize = CUBE_SIZE * CUBE_SIZE * CUBE_SIZE;
-const NUM_PIECES: usize = 13;
+use bedlam_cube_rs::*;
 
-enum Axis {
-    X,
-    Y,
-    Z,
+/// Format of the solution file
+#[derive(Default, Clone, Copy, PartialEq)]
+enum Format {
+    #[default]
+    Text,
+    Json,
+    /// One Wavefront OBJ file per solution
+    Obj,
+    /// Placements as raw bits, `write_solutions_bin`
+    Bin,
 }
 
-struct Coords(usize, usize, usize);
-
-#[derive(Default, Clone)]
-struct Solution([u64; NUM_PIECES]);
+impl std::str::FromStr for Format {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "obj" => Ok(Self::Obj),
+            "bin" => Ok(Self::Bin),
+            _ => Err(()),
+        }
+    }
+}
 
-fn pack_bit(b: bool, x: usize, y: usize, z: usize) -> u64 {
-    (b as u64) << (x * 16 + y * 4 + z)
+/// Format of the piece file
+#[derive(Clone, Copy, PartialEq)]
+enum PieceFormat {
+    /// Layers of '0' and '1' grids, `read_pieces`
+    Text,
+    /// Arrays of filled cells, `read_pieces_json`
+    Json,
 }
-fn unpack_bit(block: u64, x: usize, y: usize, z: usize) -> bool {
-    (block >> (x * 16 + y * 4 + z)) & 1 == 1
+
+impl std::str::FromStr for PieceFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
 }
 
-/// Trait for indexing into a block
-/// Mainly to support both printing a block as a u64, or as an array of bools
-trait BlockIndex<T> {
-    fn index(&self, i: T) -> bool;
+/// Order to write the unique solutions in
+#[derive(Clone, Copy, PartialEq)]
+enum SortBy {
+    /// By the placement of the first piece, so solutions that share it are adjacent
+    Piece,
+    /// By `canonical_key`
+    Canonical,
 }
 
-impl BlockIndex<Coords> for u64 {
-    fn index(&self, Coords(x, y, z): Coords) -> bool {
-        unpack_bit(*self, x, y, z)
+impl std::str::FromStr for SortBy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "piece" => Ok(Self::Piece),
+            "canonical" => Ok(Self::Canonical),
+            _ => Err(()),
+        }
     }
 }
-impl BlockIndex<Coords> for &[[[bool; 4]; 4]; 4] {
-    fn index(&self, Coords(x, y, z): Coords) -> bool {
-        self[z][y][x]
-    }
+
+/// Search algorithm to solve the puzzle with
+#[derive(Default, Clone, Copy, PartialEq)]
+enum Backend {
+    /// Backtracking over the lowest empty cell, `search`
+    #[default]
+    Backtrack,
+    /// Algorithm X with dancing links, `Dlx::search`
+    Dlx,
 }
 
-/// Quick and dirty hash for a solution
-fn hash_solution(solution: &Solution) -> u64 {
-    let mut h = 0;
-    for p in 0..NUM_PIECES {
-        h ^= solution.0[p] << p;
+impl std::str::FromStr for Backend {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "backtrack" => Ok(Self::Backtrack),
+            "dlx" => Ok(Self::Dlx),
+            _ => Err(()),
+        }
     }
-    h
 }
 
-fn print<T>(block: T)
-where
-    T: BlockIndex<Coords>,
-{
-    for y in 0..4 {
-        for z in 0..4 {
-            for x in 0..4 {
-                print!(
-                    "{}",
-                    if block.index(Coords(x, y, z)) {
-                        "#"
-                    } else {
-                        "."
+/// How much is printed about the puzzle before and during the search
+#[derive(Default, Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+    /// Only the results
+    Quiet,
+    /// A short summary of the pieces and the search
+    #[default]
+    Normal,
+    /// Every piece and its placements
+    Verbose,
+}
+
+/// Print a line if the verbosity is at least `level`
+macro_rules! log {
+    ($verbosity:expr, $level:expr, $($arg:tt)*) => {
+        if $verbosity >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+const USAGE: &str = "Usage: bedlam-cube-rs [options] [pieces] [solutions]
+
+Options:
+  --pieces <path>            Read pieces from this file, or stdin for - (default: pieces.txt)
+  --pieces-format <text|json>
+                             Format of the piece file (default: json for .json files, else text)
+  --puzzle <name>            Solve the puzzle with this name in a text piece file with
+                             several puzzles (default: the first)
+  --grid-chars <filled><empty>
+                             Characters of the filled and empty cells in a text piece
+                             file, e.g. '#.' (default: 10)
+  --solutions <path>         Write solutions to this file (default: solutions.txt)
+  --format <text|json|obj|bin>
+                             Format of the solution file (default: text)
+  --out-dir <dir>            Write one file per solution to this directory, for obj
+  --size <n>                 Side length of the cube, at most 5 (default: 4)
+  --box <WxHxD>              Fill a box with these side lengths instead of a cube,
+                             with at most 128 cells
+  --color                    Print pieces and solutions in color, if stdout is a terminal
+  --burr-tools <path>        Also write the puzzle and solutions as a Burr Tools file
+  --first                    Stop the search after the first solution
+  --stop-after <n>           Stop the search after n solutions
+  --max-unique <n>           Stop the search after n unique solutions
+  --timeout <s>              Stop the search after s seconds and report the solutions so far
+  --limit-depth <n>          Stop after placing n pieces and report the partial states
+  --sub-puzzle <k>           Solve once and remove k pieces to create a new puzzle
+  --seed <n>                 Seed for the random number generator
+  --random [seed]            Try pieces and placements in a random order, often finding a
+                             first solution faster, the seed is printed to repeat a run
+  --threads <n>              Search on n threads (default: 1)
+  --solver <backtrack|dlx>   Search algorithm to use (default: backtrack)
+  --quick-feasible           Prune the search with a quick feasibility check
+  --heuristic <lowest-bit|most-constrained|corner-first>
+                             Cell to branch on, the lowest empty one, the one with the
+                             fewest placements that fit or the one closest to a corner
+                             (default: most-constrained)
+  --piece-order <file|fewest-placements>
+                             Order to try the pieces in at each node, as in the piece file
+                             or the ones with the fewest placements that fit first
+                             (default: fewest-placements)
+  --prune-regions            Prune states with an empty region no unused pieces can fill
+  --max-placements-per-piece <n>
+                             Only search the first n placements of each piece, for quick
+                             runs. The search is no longer exhaustive and can miss solutions
+  --forward-checking         Keep count of the placements that fit in each empty cell and
+                             prune states where a cell has none
+  --count                    Only count solutions, without storing them
+  --count-unique             Only count unique solutions, without storing them
+  --no-unique                Write every solution found, without filtering out the
+                             rotations of solutions found before
+  --no-bit-map               Look up placements on demand, saving memory
+  --profile-branching        Report the branching factor at each depth
+  --profile-pieces           Report how often each piece is placed and leads to a dead end
+  --histogram                Report how many solutions were found in each second
+  --fix <piece>:<placement>  Only find solutions with the piece at this placement, given as
+                             a hex bitmask like the checkpoint picks, can be repeated
+  --seed-solution <path>:<k>
+                             Fix pieces 0 to k - 1 at their placements in the first
+                             solution of this file and find the completions of the rest
+  --require <piece>:<placement>
+                             Only search the branches that place the piece at this
+                             placement, without fixing it up front, can be repeated
+  --exclude <ids>            Leave out these pieces, given as a comma separated list, and
+                             leave the cells they would fill empty
+  --shard <index>/<count>    Only search one shard of the top level branches
+  --shard-output <dir>       Write the solutions of the shard to this directory
+  --merge-shards <dir>       Merge the shard files in this directory
+  --verify <path>            Check the solutions in this file, or a single solution grid,
+                             against the pieces, exits with 1 if one isn't valid
+  --animate <index>          Show the solution with this index in the solutions file one z
+                             layer at a time, or write a file per layer with --out-dir
+  --diff <a> <b>             Compare two solution files up to rotation and report the
+                             solutions only in one of them, exits with 1 if they differ
+  --no-symmetry-breaking     Search all rotations of every solution
+  --sort-by <piece|canonical>
+                             Sort the unique solutions by the placement of the first
+                             piece or by their canonical form before writing them
+                             (default: the order they were found in)
+  --with-mirror              Count mirror images of a solution as the same solution
+  --no-mirror                Count mirror images as different solutions, only rotations
+                             are the same (default). Either way, solutions that only
+                             swap pieces of the same shape are the same
+  --stats-json <path>        Write the number of permutations and solutions and the time
+                             taken to this file as JSON
+  --stream                   Write unique solutions to the solution file as they are found
+  --checkpoint <path>        Periodically save the search state to this file
+  --checkpoint-interval <s>  Seconds between checkpoints (default: 60)
+  --resume <path>            Resume the search from a checkpoint file
+  --cache <dir>              Load the placements of the pieces from this directory if they
+                             were generated before, and save them there otherwise
+  --no-cache                 Always generate the placements (default)
+  --allow-duplicates         Don't warn about pieces with the same shape
+  --interactive              Place the pieces by hand, reading commands from stdin
+  --bench                    Count the solutions with each combination of --heuristic,
+                             --prune-regions and symmetry breaking and compare the time
+                             and permutations of each, --timeout applies to each run
+  --estimate                 Print the number of placements and branches at the first
+                             cell as an estimate of the size of the search, without solving
+  --dump-pieces              Print every piece in its canonical position with its number
+                             of orientations and placements, without solving
+  -q, --quiet                Only print the results, without the summary and progress
+  -v, --verbose              Also print every piece and the masks of its placements
+  -V, --version              Print the version
+  -h, --help                 Print this message
+
+A value can also be given after an '=', e.g. --timeout=60.";
+
+/// Command line options
+#[derive(Default)]
+struct Args {
+    /// Path to the piece definitions
+    pieces: String,
+    /// Format of the piece file, picked from the file extension if not given
+    pieces_format: Option<PieceFormat>,
+    /// Order of the unique solutions in the output, None for the order they were found in
+    sort_by: Option<SortBy>,
+    /// Name of the puzzle to solve in a piece file with several puzzles
+    puzzle: Option<String>,
+    /// Characters of the cells in a text piece file
+    grid_chars: GridChars,
+    /// Path to write the solutions to
+    solutions: String,
+    /// Format of the solution file
+    format: Format,
+    /// Search algorithm
+    solver: Backend,
+    /// Directory to write one file per solution to, for formats that need it
+    out_dir: Option<String>,
+    /// Side length of the cube
+    size: usize,
+    /// Side lengths of the box along x, y and z, if filling a box instead of a cube
+    dims: Option<[usize; 3]>,
+    /// Print usage and exit
+    help: bool,
+    /// Print the version and exit
+    version: bool,
+    /// Print the canonical form, orientations and placements of every piece and exit
+    dump_pieces: bool,
+    /// Print an estimate of the size of the search and exit
+    estimate: bool,
+    /// Let the user place the pieces by hand instead of solving
+    interactive: bool,
+    /// Compare the search with several combinations of options instead of solving once
+    bench: bool,
+    /// How much to print besides the results
+    verbosity: Verbosity,
+    /// Print pieces and solutions with a color per piece when writing to a terminal
+    color: bool,
+    /// Also write the puzzle and its solutions to this Burr Tools file
+    burr_tools: Option<String>,
+    /// Stop the search after this many solutions
+    stop_after: Option<usize>,
+    /// Stop the search after this many unique solutions
+    max_unique: Option<usize>,
+    /// Stop the search after this many seconds
+    timeout: Option<u64>,
+    /// Stop the search after placing this many pieces and report the partial states
+    limit_depth: Option<usize>,
+    /// Solve once and remove this many pieces from the solution to create a new puzzle
+    sub_puzzle: Option<usize>,
+    /// Seed for the random number generator
+    seed: Option<u64>,
+    /// Try the pieces and placements in a random order
+    random: bool,
+    /// Number of threads to search on
+    threads: usize,
+    /// Prune the search with the quick feasibility check
+    quick_feasible: bool,
+    /// How the search picks the cell to branch on
+    heuristic: CellHeuristic,
+    /// Order the search tries the pieces in at each node
+    piece_order: PieceOrder,
+    /// Prune states with an empty region that can't be filled by the unused pieces
+    prune_regions: bool,
+    /// Prune states with an empty cell that no placement fits in, counted incrementally
+    forward_checking: bool,
+    /// Only search the first n placements of each piece, which makes the search incomplete
+    max_placements_per_piece: Option<usize>,
+    /// Only count the solutions, without storing or writing them
+    count: bool,
+    /// Write every solution found instead of only the unique ones
+    no_unique: bool,
+    /// Only count unique solutions during the search, without storing or writing them
+    count_unique: bool,
+    /// Look up placements on demand instead of precomputing the bit map, saving memory
+    no_bit_map: bool,
+    /// Measure the average number of choices at each depth of the search
+    profile_branching: bool,
+    /// Count the placements tried and the dead ends of each piece
+    profile_pieces: bool,
+    /// Record when each solution is found and print a histogram of them over time
+    histogram: bool,
+    /// Pieces fixed at a placement each
+    fix: Vec<(usize, BitCube)>,
+    /// Solution file and number of pieces, the first pieces of the first solution in the
+    /// file are fixed at their placements
+    seed_solution: Option<(String, usize)>,
+    /// Pieces only allowed at a placement each, pruning the branches that place them elsewhere
+    require: Vec<(usize, BitCube)>,
+    /// Pieces to leave out of the puzzle
+    exclude: Vec<usize>,
+    /// Only search shard `index` out of `count` equally sized parts of the top level branches
+    shard: Option<(usize, usize)>,
+    /// Write the solutions of the shard to a shard file in this directory
+    shard_output: Option<String>,
+    /// Merge the shard files in this directory into a single solution file
+    merge_shards: Option<String>,
+    /// Check the solutions in this file against the pieces instead of solving
+    verify: Option<String>,
+    /// Show this solution of the solutions file layer by layer instead of solving
+    animate: Option<usize>,
+    /// Compare these two solution files instead of solving
+    diff: Option<(String, String)>,
+    /// Consider solutions that are reflections of each other the same when filtering unique solutions
+    with_mirror: bool,
+    /// Write unique solutions to the solution file as they are found instead of at the end
+    stream: bool,
+    /// Write the metrics of the run to this file as JSON
+    stats_json: Option<String>,
+    /// Periodically write the state of the search to this file
+    checkpoint: Option<String>,
+    /// Seconds between checkpoints
+    checkpoint_interval: u64,
+    /// Resume the search from this checkpoint file
+    resume: Option<String>,
+    /// Directory to load previously generated placements from and save new ones to
+    cache: Option<String>,
+    /// Don't warn about pieces with the same shape
+    allow_duplicates: bool,
+    /// Search all 24 rotations of every solution instead of restricting one piece
+    no_symmetry_breaking: bool,
+}
+
+/// Parse the value following a flag
+fn flag_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Parse a `<piece>:<placement>` value following a flag, with the placement in hex
+fn piece_placement(flag: &str, value: Option<String>) -> Result<(usize, BitCube), String> {
+    let value: String = flag_value(flag, value)?;
+    let parsed = value.split_once(':').and_then(|(piece, placement)| {
+        let placement = placement.trim_start_matches("0x");
+        Some((
+            piece.parse().ok()?,
+            BitCube(u128::from_str_radix(placement, 16).ok()?),
+        ))
+    });
+    parsed.ok_or_else(|| format!("Invalid value for {}: {}", flag, value))
+}
+
+impl Args {
+    fn parse(iter: impl Iterator<Item = String>) -> Result<Self, String> {
+        // --flag=value is the same as --flag value
+        let mut iter = iter
+            .flat_map(|arg| match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    vec![flag.to_string(), value.to_string()]
+                }
+                _ => vec![arg],
+            })
+            .peekable();
+        let mut args = Self {
+            pieces: "pieces.txt".to_string(),
+            solutions: "solutions.txt".to_string(),
+            size: 4,
+            threads: 1,
+            checkpoint_interval: 60,
+            ..Default::default()
+        };
+        let mut positional = Vec::new();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-h" | "--help" => args.help = true,
+                "-V" | "--version" => args.version = true,
+                "--dump-pieces" => args.dump_pieces = true,
+                "--estimate" => args.estimate = true,
+                "--interactive" => args.interactive = true,
+                "--bench" => args.bench = true,
+                "-q" | "--quiet" => args.verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => args.verbosity = Verbosity::Verbose,
+                "--pieces" => args.pieces = flag_value(&arg, iter.next())?,
+                "--color" => args.color = true,
+                "--sort-by" => args.sort_by = Some(flag_value(&arg, iter.next())?),
+                "--pieces-format" => args.pieces_format = Some(flag_value(&arg, iter.next())?),
+                "--puzzle" => args.puzzle = Some(flag_value(&arg, iter.next())?),
+                "--grid-chars" => args.grid_chars = flag_value(&arg, iter.next())?,
+                "--solutions" => args.solutions = flag_value(&arg, iter.next())?,
+                "--format" => args.format = flag_value(&arg, iter.next())?,
+                "--solver" => args.solver = flag_value(&arg, iter.next())?,
+                "--out-dir" => args.out_dir = Some(flag_value(&arg, iter.next())?),
+                "--size" => {
+                    args.size = flag_value(&arg, iter.next())?;
+                    if !(1..=MAX_CUBE_SIZE).contains(&args.size) {
+                        return Err(format!("Cube size must be between 1 and {}", MAX_CUBE_SIZE));
                     }
-                );
+                }
+                "--box" => {
+                    let dims: String = flag_value(&arg, iter.next())?;
+                    let sides: Vec<_> = dims.split('x').map(|side| side.parse().ok()).collect();
+                    let [x, y, z] = match sides[..] {
+                        [Some(x), Some(y), Some(z)] => [x, y, z],
+                        _ => return Err(format!("Invalid value for {}: {}", arg, dims)),
+                    };
+                    args.dims = Some([x, y, z]);
+                    if x == 0
+                        || y == 0
+                        || z == 0
+                        || x.saturating_mul(y).saturating_mul(z) > MAX_CELLS
+                    {
+                        return Err(format!(
+                            "Box sides must be at least 1, with at most {} cells",
+                            MAX_CELLS
+                        ));
+                    }
+                }
+                "--burr-tools" => args.burr_tools = Some(flag_value(&arg, iter.next())?),
+                "--first" => args.stop_after = Some(1),
+                "--stop-after" => args.stop_after = Some(flag_value(&arg, iter.next())?),
+                "--max-unique" => args.max_unique = Some(flag_value(&arg, iter.next())?),
+                "--timeout" => args.timeout = Some(flag_value(&arg, iter.next())?),
+                "--limit-depth" => args.limit_depth = Some(flag_value(&arg, iter.next())?),
+                "--sub-puzzle" => args.sub_puzzle = Some(flag_value(&arg, iter.next())?),
+                "--seed" => args.seed = Some(flag_value(&arg, iter.next())?),
+                "--random" => {
+                    args.random = true;
+                    // The seed is optional, anything else is the next argument
+                    if let Some(seed) = iter.peek().and_then(|value| value.parse().ok()) {
+                        iter.next();
+                        args.seed = Some(seed);
+                    }
+                }
+                "--threads" => args.threads = flag_value(&arg, iter.next())?,
+                "--quick-feasible" => args.quick_feasible = true,
+                "--prune-regions" => args.prune_regions = true,
+                "--forward-checking" => args.forward_checking = true,
+                "--no-unique" => args.no_unique = true,
+                "--max-placements-per-piece" => {
+                    args.max_placements_per_piece = Some(flag_value(&arg, iter.next())?)
+                }
+                "--heuristic" => args.heuristic = flag_value(&arg, iter.next())?,
+                "--piece-order" => args.piece_order = flag_value(&arg, iter.next())?,
+                "--count" => args.count = true,
+                "--count-unique" => args.count_unique = true,
+                "--no-bit-map" => args.no_bit_map = true,
+                "--profile-branching" => args.profile_branching = true,
+                "--profile-pieces" => args.profile_pieces = true,
+                "--histogram" => args.histogram = true,
+                "--fix" => args.fix.push(piece_placement(&arg, iter.next())?),
+                "--seed-solution" => {
+                    let value: String = flag_value(&arg, iter.next())?;
+                    let parsed = value
+                        .rsplit_once(':')
+                        .and_then(|(path, k)| Some((path.to_string(), k.parse().ok()?)));
+                    args.seed_solution = Some(
+                        parsed.ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?,
+                    );
+                }
+                "--require" => args.require.push(piece_placement(&arg, iter.next())?),
+                "--exclude" => {
+                    let exclude: String = flag_value(&arg, iter.next())?;
+                    args.exclude = exclude
+                        .split(',')
+                        .map(|id| id.trim().parse().ok())
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| format!("Invalid value for {}: {}", arg, exclude))?;
+                }
+                "--shard" => {
+                    let shard: String = flag_value(&arg, iter.next())?;
+                    args.shard = shard
+                        .split_once('/')
+                        .and_then(|(index, count)| Some((index.parse().ok()?, count.parse().ok()?)))
+                        .filter(|(index, count)| index < count);
+                    if args.shard.is_none() {
+                        return Err(format!("Invalid value for {}: {}", arg, shard));
+                    }
+                }
+                "--shard-output" => args.shard_output = Some(flag_value(&arg, iter.next())?),
+                "--merge-shards" => args.merge_shards = Some(flag_value(&arg, iter.next())?),
+                "--verify" => args.verify = Some(flag_value(&arg, iter.next())?),
+                "--animate" => args.animate = Some(flag_value(&arg, iter.next())?),
+                "--diff" => {
+                    let a = flag_value(&arg, iter.next())?;
+                    args.diff = Some((a, flag_value(&arg, iter.next())?));
+                }
+                "--with-mirror" => args.with_mirror = true,
+                "--no-mirror" => args.with_mirror = false,
+                "--stream" => args.stream = true,
+                "--stats-json" => args.stats_json = Some(flag_value(&arg, iter.next())?),
+                "--checkpoint" => args.checkpoint = Some(flag_value(&arg, iter.next())?),
+                "--checkpoint-interval" => {
+                    args.checkpoint_interval = flag_value(&arg, iter.next())?
+                }
+                "--resume" => args.resume = Some(flag_value(&arg, iter.next())?),
+                "--cache" => args.cache = Some(flag_value(&arg, iter.next())?),
+                "--no-cache" => args.cache = None,
+                "--allow-duplicates" => args.allow_duplicates = true,
+                "--no-symmetry-breaking" => args.no_symmetry_breaking = true,
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("Unknown argument: {}", arg))
+                }
+                _ => positional.push(arg),
             }
-            print!("    ");
         }
-        println!();
+
+        let mut positional = positional.into_iter();
+        if let Some(pieces) = positional.next() {
+            args.pieces = pieces;
+        }
+        if let Some(solutions) = positional.next() {
+            args.solutions = solutions;
+        }
+        if let Some(arg) = positional.next() {
+            return Err(format!("Unexpected argument: {}", arg));
+        }
+        if args.format == Format::Obj && args.out_dir.is_none() {
+            return Err("--format obj requires --out-dir".to_string());
+        }
+        if args.no_unique && (args.count_unique || args.max_unique.is_some() || args.stream) {
+            return Err(
+                "--no-unique can't be combined with --count-unique, --max-unique or --stream"
+                    .to_string(),
+            );
+        }
+        if args.solver == Backend::Dlx
+            && (args.threads > 1
+                || args.limit_depth.is_some()
+                || args.quick_feasible
+                || args.prune_regions
+                || args.forward_checking
+                || args.shard.is_some()
+                || args.random
+                || !args.require.is_empty())
+        {
+            return Err(
+                "--solver dlx can't be combined with --threads, --limit-depth, --quick-feasible, --prune-regions, --forward-checking, --shard, --random or --require"
+                    .to_string(),
+            );
+        }
+        if args.stream
+            && (args.threads > 1
+                || args.checkpoint.is_some()
+                || args.resume.is_some()
+                || args.format != Format::Text
+                || args.burr_tools.is_some())
+        {
+            return Err(
+                "--stream can't be combined with --threads, --checkpoint, --resume, --format or --burr-tools"
+                    .to_string(),
+            );
+        }
+        if !args.exclude.is_empty()
+            && (args.solver == Backend::Dlx
+                || args.quick_feasible
+                || args.prune_regions
+                || args.checkpoint.is_some()
+                || args.resume.is_some())
+        {
+            return Err(
+                "--exclude can't be combined with --solver dlx, --quick-feasible, --prune-regions, --checkpoint or --resume"
+                    .to_string(),
+            );
+        }
+        if (args.checkpoint.is_some() || args.resume.is_some())
+            && (args.threads > 1
+                || args.solver == Backend::Dlx
+                || args.count_unique
+                || args.max_unique.is_some())
+        {
+            return Err(
+                "--checkpoint and --resume can't be combined with --threads, --solver dlx, --count-unique or --max-unique"
+                    .to_string(),
+            );
+        }
+        Ok(args)
     }
 }
 
-// Write a solution to stream
-fn write_solution(
-    picks: &Solution,
-    stream: &mut impl std::io::Write,
+/// Create a file, with the path in the error message
+fn create_file(path: impl AsRef<std::path::Path>) -> Result<std::fs::File, std::io::Error> {
+    let path = path.as_ref();
+    std::fs::File::create(path)
+        .map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+}
+
+/// Sort the solutions for --sort-by
+///
+/// Both sorts are stable, solutions with the same key stay in the order they were found in.
+fn sort_solutions(puzzle: &Puzzle, solutions: &mut [Solution], sort_by: SortBy, mirror: bool) {
+    match sort_by {
+        SortBy::Piece => solutions.sort_by_key(|solution| solution.0[0]),
+        SortBy::Canonical => {
+            solutions.sort_by_cached_key(|solution| canonical_key(puzzle, solution, mirror))
+        }
+    }
+}
+
+/// Write the solutions to the shard file, or to the solution files and Burr Tools file
+///
+/// `branches` are the top level branches searched, out of `num_branches`.
+fn write_results(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solutions: &[Solution],
+    args: &Args,
+    branches: std::ops::Range<usize>,
+    num_branches: usize,
 ) -> Result<(), std::io::Error> {
-    // Labels for pieces: A, B, C, ...
-    let mut arr = [[['0'; 4]; 4]; 4];
+    if let Some(dir) = &args.shard_output {
+        let (index, count) = args.shard.unwrap_or((0, 1));
+        let path = std::path::Path::new(dir).join(format!("shard-{}-of-{}.txt", index, count));
+        let mut file = create_file(path)?;
+        writeln!(
+            file,
+            "# Shard {}/{}, branches {}..{} of {}",
+            index, count, branches.start, branches.end, num_branches
+        )?;
+        write_solutions(puzzle, pieces, solutions, &mut file)?;
+    } else {
+        write_solution_files(puzzle, pieces, solutions, args)?;
+    }
 
-    for p in 0..NUM_PIECES {
-        let label = (p as u8 + b'A') as char;
+    if let Some(path) = &args.burr_tools {
+        let mut file = create_file(path)?;
+        write_burr_tools(puzzle, pieces, solutions, &mut file)?;
+    }
+    Ok(())
+}
 
-        for z in 0..4 {
-            for y in 0..4 {
-                for x in 0..4 {
-                    if unpack_bit(picks.0[p], x, y, z) {
-                        arr[z][y][x] = label;
-                    }
-                }
+/// Write solutions in the requested format, either to the solution file or as one file
+/// per solution in the output directory
+fn write_solution_files(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solutions: &[Solution],
+    args: &Args,
+) -> Result<(), std::io::Error> {
+    match args.format {
+        Format::Text => {
+            let mut file = create_file(&args.solutions)?;
+            write_solutions(puzzle, pieces, solutions, &mut file)
+        }
+        Format::Json => {
+            let mut file = create_file(&args.solutions)?;
+            write_solutions_json(puzzle, pieces, solutions, &mut file)
+        }
+        Format::Bin => {
+            let mut file = std::io::BufWriter::new(create_file(&args.solutions)?);
+            write_solutions_bin(puzzle, solutions, &mut file)?;
+            file.flush()
+        }
+        Format::Obj => {
+            let dir = std::path::Path::new(args.out_dir.as_deref().unwrap_or("."));
+            std::fs::create_dir_all(dir)?;
+            for (i, solution) in solutions.iter().enumerate() {
+                let mut file = create_file(dir.join(format!("solution-{}.obj", i)))?;
+                write_solution_obj(puzzle, pieces, solution, &mut file)?;
             }
+            Ok(())
         }
     }
+}
 
-    for z in 0..4 {
-        for y in 0..4 {
-            for x in 0..4 {
-                write!(stream, "{}", arr[z][y][x])?;
-            }
-            if y != 3 {
-                write!(stream, "    ")?;
-            }
+/// Pieces of the puzzle picked by `--puzzle` out of the puzzles of the piece file, the
+/// first if it isn't given
+fn select_puzzle(puzzles: Vec<NamedPieces>, args: &Args) -> Result<Vec<Piece>, Error> {
+    let names: Vec<_> = puzzles
+        .iter()
+        .filter_map(|(name, _)| name.clone())
+        .collect();
+    let index = match &args.puzzle {
+        Some(wanted) => puzzles
+            .iter()
+            .position(|(name, _)| name.as_ref() == Some(wanted))
+            .ok_or_else(|| {
+                Error::Parse(format!(
+                    "{}: no puzzle named {}, the puzzles are: {}",
+                    args.pieces,
+                    wanted,
+                    names.join(", ")
+                ))
+            })?,
+        None => 0,
+    };
+    let (name, pieces) = puzzles.into_iter().nth(index).unwrap();
+    if let Some(name) = name {
+        log!(args.verbosity, Verbosity::Normal, "Puzzle {}", name);
+        if args.puzzle.is_none() && names.len() > 1 {
+            log!(
+                args.verbosity,
+                Verbosity::Normal,
+                "The piece file has {} puzzles ({}), pick another one with --puzzle",
+                names.len(),
+                names.join(", ")
+            );
+        }
+    }
+    Ok(pieces)
+}
+
+/// Print a solution or partial state to stdout, in color if `color` is set
+fn print_solution(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solution: &Solution,
+    color: bool,
+) -> Result<(), std::io::Error> {
+    let mut stdout = std::io::stdout();
+    if color {
+        write_solution_color(puzzle, pieces, solution, &mut stdout)
+    } else {
+        write_solution(puzzle, pieces, solution, &mut stdout)
+    }
+}
+
+/// Show a solution one z layer at a time, as frames of an animation peeling the cube
+///
+/// With `--out-dir` each layer is written to its own file instead, named after the index
+/// of the solution and the layer.
+fn animate(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    solution: &Solution,
+    index: usize,
+    args: &Args,
+    color: bool,
+) -> Result<(), Error> {
+    if let Some(dir) = &args.out_dir {
+        let dir = std::path::Path::new(dir);
+        std::fs::create_dir_all(dir)?;
+        for z in 0..puzzle.dims[2] {
+            let path = dir.join(format!("solution-{}-layer-{}.txt", index, z));
+            let mut file = create_file(&path)?;
+            write_solution_layer(puzzle, pieces, solution, z, false, &mut file)?;
         }
-        writeln!(stream)?;
+        println!(
+            "Wrote {} layers of solution {} to {}",
+            puzzle.dims[2],
+            index,
+            dir.display()
+        );
+        return Ok(());
+    }
+    let terminal = std::io::stdout().is_terminal();
+    let mut stdout = std::io::stdout();
+    for z in 0..puzzle.dims[2] {
+        if z > 0 {
+            stdout.flush()?;
+            std::thread::sleep(ANIMATION_FRAME);
+        }
+        if terminal {
+            // Draw each frame over the previous one
+            print!("\x1b[2J\x1b[H");
+        } else if z > 0 {
+            println!();
+        }
+        println!("Solution {}, layer {} of {}", index, z + 1, puzzle.dims[2]);
+        write_solution_layer(puzzle, pieces, solution, z, color, &mut stdout)?;
     }
     Ok(())
 }
 
-/// Read pieces from file
+/// Time each frame of `--animate` is shown for
+const ANIMATION_FRAME: std::time::Duration = std::time::Duration::from_millis(800);
+
+/// Write the metrics of a run as a JSON object, to track performance across commits
 ///
-/// File format:
-/// 4x4x2 blocks, each piece starting with a piece id (0, 1, 2, ...)
-/// z y x: 0123
-/// 0 0    0000
-/// 0 1    0000
-/// 0 2    0000
-/// 0 3    0000
-/// 1 0    0000
-/// 1 1    0000
-/// 1 2    0000
-/// 1 3    0000
+/// `num_unique` is null when unique solutions weren't counted.
+fn write_stats_json(
+    path: &str,
+    stats: &Stats,
+    num_unique: Option<usize>,
+    seconds: f64,
+    stopped: bool,
+) -> Result<(), std::io::Error> {
+    let mut file = create_file(path)?;
+    writeln!(file, "{{")?;
+    writeln!(file, "  \"permutations\": {},", stats.num_permutations)?;
+    writeln!(file, "  \"solutions\": {},", stats.num_solutions)?;
+    match num_unique {
+        Some(num_unique) => writeln!(file, "  \"unique_solutions\": {},", num_unique)?,
+        None => writeln!(file, "  \"unique_solutions\": null,")?,
+    }
+    writeln!(file, "  \"seconds\": {},", seconds)?;
+    match stats.first_solution_time {
+        Some(time) => writeln!(file, "  \"first_solution_seconds\": {},", time)?,
+        None => writeln!(file, "  \"first_solution_seconds\": null,")?,
+    }
+    writeln!(
+        file,
+        "  \"permutations_per_second\": {},",
+        stats.num_permutations as f64 / seconds.max(f64::EPSILON)
+    )?;
+    writeln!(file, "  \"stopped\": {}", stopped)?;
+    writeln!(file, "}}")
+}
+
+/// Format a number of bytes with a binary unit, e.g. "1.50 MiB"
+fn format_bytes(bytes: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", size, units[unit])
+    }
+}
+
+/// Placements of the pieces from the cache directory, generated and saved to the cache
+/// if they aren't there yet
 ///
-/// E.g.:
-/// # 0
-/// 0100
-/// 1110
-/// 0100
-/// 0000
-/// 0000
-/// 0000
-/// 0000
-/// 0000
-/// # 1
-/// ...
-fn read_pieces(path: &str) -> Result<Vec<u64>, std::io::Error> {
-    let contents = std::fs::read_to_string(path)?;
-
-    let mut blocks = Vec::new();
-    let mut lines = contents.lines();
-    loop {
-        if lines.next().is_none() {
-            break;
+/// The file is named after the hash of the pieces and the ids of the fixed pieces, a cache
+/// file for other pieces with the same hash is overwritten.
+fn cached_placements(
+    puzzle: &Puzzle,
+    pieces: &[BitCube],
+    fixed: &[bool],
+    dir: &str,
+    verbosity: Verbosity,
+) -> Result<Vec<Vec<BitCube>>, std::io::Error> {
+    let fixed_ids: String = (0..fixed.len())
+        .filter(|piece| fixed[*piece])
+        .map(|piece| format!("-{}", piece))
+        .collect();
+    let path = std::path::Path::new(dir).join(format!(
+        "placements-{:016x}{}.txt",
+        pieces_hash(puzzle, pieces),
+        if fixed_ids.is_empty() {
+            String::new()
+        } else {
+            format!("-fixed{}", fixed_ids)
         }
-
-        let mut block = 0;
-        for z in 0..2 {
-            for y in 0..4 {
-                let line = lines.next().unwrap();
-                for (x, c) in line.chars().enumerate() {
-                    if c == '1' {
-                        block |= pack_bit(true, x, y, z);
-                    }
-                }
-            }
+    ));
+    if path.exists() {
+        if let Some(piece_placements) = read_placements(puzzle, pieces, &path)? {
+            log!(
+                verbosity,
+                Verbosity::Normal,
+                "Loaded placements from {}",
+                path.display()
+            );
+            return Ok(piece_placements);
         }
-        blocks.push(block);
     }
-    Ok(blocks)
+    let piece_placements = generate_all_placements(puzzle, pieces, fixed);
+    std::fs::create_dir_all(dir)?;
+    let mut file = std::io::BufWriter::new(create_file(&path)?);
+    write_placements(puzzle, pieces, &piece_placements, &mut file)?;
+    file.flush()?;
+    log!(
+        verbosity,
+        Verbosity::Normal,
+        "Saved placements to {}",
+        path.display()
+    );
+    Ok(piece_placements)
 }
 
-/// Rotate piece by 90 degres around the given axis
-fn rotate_piece_90(piece: u64, axis: Axis) -> u64 {
-    let mut new_piece = 0;
-    for z in 0..4 {
-        for y in 0..4 {
-            for x in 0..4 {
-                let (sx, sy, sz) = match axis {
-                    Axis::X => (x, 3 - z, y),
-                    Axis::Y => (3 - z, y, x),
-                    Axis::Z => (3 - y, x, z),
-                };
-                new_piece |= pack_bit(piece.index(Coords(sx, sy, sz)), x, y, z);
-            }
-        }
+/// Print the sizes the search starts from, for a rough idea of how long it will take
+///
+/// These are the number of placements of the pieces after symmetry breaking and fixing,
+/// the top level branches at the first cell the search branches on and an upper bound on
+/// the number of ways to place every piece, ignoring overlaps.
+fn print_estimate(
+    puzzle: &Puzzle,
+    placements: &Placements,
+    first_cell: usize,
+    num_branches: usize,
+) {
+    let counts: Vec<_> = placements.piece_placements.iter().map(Vec::len).collect();
+    println!("Placements: {}", counts.iter().sum::<usize>());
+    for (piece, count) in counts.iter().enumerate() {
+        println!("  Piece {}: {}", piece, count);
     }
-    new_piece
+    let (layer, row) = (puzzle.dims[1] * puzzle.dims[2], puzzle.dims[2]);
+    let reaching = (0..puzzle.num_pieces)
+        .filter(|piece| placements.for_cell(first_cell, *piece).next().is_some())
+        .count();
+    println!(
+        "First cell: ({}, {}, {}), {} branches from {} of {} pieces",
+        first_cell / layer,
+        first_cell / row % puzzle.dims[1],
+        first_cell % row,
+        num_branches,
+        reaching,
+        puzzle.num_pieces
+    );
+    let log_product: f64 = counts.iter().map(|count| (*count as f64).log10()).sum();
+    println!(
+        "Upper bound, placing every piece anywhere: 10^{:.1} combinations",
+        log_product
+    );
 }
 
-/// Translate the piece in the cube by dx, dy, dz
-fn translate(piece: u64, dx: i32, dy: i32, dz: i32) -> u64 {
-    let mut new_piece = 0;
-    for z in 0..4 {
-        for y in 0..4 {
-            for x in 0..4 {
-                let sx = x + dx;
-                let sy = y + dy;
-                let sz = z + dz;
-                if sx < 4 && sy < 4 && sz < 4 && sx >= 0 && sy >= 0 && sz >= 0 {
-                    new_piece |= pack_bit(
-                        piece.index(Coords(x as usize, y as usize, z as usize)),
-                        sx as usize,
-                        sy as usize,
-                        sz as usize,
-                    );
+/// Count the solutions with each combination of the cell heuristic, region pruning and
+/// symmetry breaking, printing the time and the number of permutations of each run
+///
+/// The other search options are taken from the arguments, each run is stopped after
+/// `--timeout` seconds if given. Symmetry breaking is only tried if `symmetry_breaking`.
+fn bench(puzzle: &Puzzle, piece_placements: &[Vec<BitCube>], args: &Args, symmetry_breaking: bool) {
+    println!("Heuristic         Regions  Symmetry     Solutions  Permutations     Seconds");
+    for heuristic in [CellHeuristic::LowestBit, CellHeuristic::MostConstrained] {
+        for prune_regions in [false, true] {
+            for break_symmetry_on in [false, true] {
+                if break_symmetry_on && !symmetry_breaking {
+                    continue;
+                }
+                let mut piece_placements = piece_placements.to_vec();
+                if break_symmetry_on {
+                    break_symmetry(puzzle, &mut piece_placements);
                 }
+                let placements = Placements::new(*puzzle, piece_placements, !args.no_bit_map);
+                let mut config = SolverConfig::new()
+                    .heuristic(heuristic)
+                    .piece_order(args.piece_order)
+                    .quick_feasible(args.quick_feasible)
+                    .prune_regions(prune_regions)
+                    .forward_checking(args.forward_checking);
+                if let Some(timeout) = args.timeout {
+                    config = config.timeout(std::time::Duration::from_secs(timeout));
+                }
+                let options = SearchOptions {
+                    required: args.require.clone(),
+                    ..config.search_options(puzzle.num_pieces)
+                };
+                let start = std::time::Instant::now();
+                let mut stats = config.stats();
+                let mut picks = Solution(vec![BitCube::default(); puzzle.num_pieces]);
+                search(
+                    BitCube::default(),
+                    0,
+                    &placements,
+                    &mut picks,
+                    &options,
+                    &mut stats,
+                    &mut |_| {},
+                );
+                println!(
+                    "{:<16}  {:<7}  {:<8}  {:>12}  {:>12}  {:>10.3}{}",
+                    if heuristic == CellHeuristic::LowestBit {
+                        "lowest-bit"
+                    } else {
+                        "most-constrained"
+                    },
+                    if prune_regions { "on" } else { "off" },
+                    if break_symmetry_on { "on" } else { "off" },
+                    stats.num_solutions,
+                    stats.num_permutations,
+                    start.elapsed().as_secs_f64(),
+                    if stats.timed_out { " (timed out)" } else { "" }
+                );
             }
         }
     }
-    new_piece
 }
 
-/// Generate all unique placements (with all possible rotations and translation) of a piece
-fn generate_placements(piece: u64) -> Vec<u64> {
-    let mut piece = piece;
-    // number of bits in a piece, should always be the same
-    // if not, the piece has been shifted outside the cube
-    let num_bits = piece.count_ones();
-
-    let mut set = std::collections::HashSet::new();
-    for _ in 0..4 {
-        for _ in 0..4 {
-            for _ in 0..4 {
-                piece = rotate_piece_90(piece, Axis::X);
-                set.insert(piece);
-            }
-            piece = rotate_piece_90(piece, Axis::Y);
-            set.insert(piece);
-        }
-        piece = rotate_piece_90(piece, Axis::Z);
-        set.insert(piece);
-    }
-    for piece in set.clone().into_iter() {
-        for z in -4..4 {
-            for y in -4..4 {
-                for x in -4..4 {
-                    let piece2 = translate(piece, x, y, z);
-                    if piece2.count_ones() == num_bits {
-                        set.insert(piece2);
+const INTERACTIVE_HELP: &str = "Commands:
+  show                       Print the cube with the pieces placed so far
+  fits <piece> <x> <y> <z>   List the placements of the piece that cover the cell and fit
+  place <piece> <x> <y> <z> [n]
+                             Place the piece covering the cell, with the n-th placement
+                             listed by fits if more than one fits
+  undo                       Take back the last placement
+  help                       Print this message
+  quit                       Stop playing";
+
+/// Let the user solve the puzzle by placing pieces by hand, reading commands from stdin
+///
+/// Uses the placements of every piece without symmetry breaking, so that any placement
+/// can be picked, and checks the solution once the cube is full.
+fn interactive(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    placements: &Placements,
+    color: bool,
+) -> Result<(), Error> {
+    let mut state = BitCube::default();
+    let mut used_pieces = 0u64;
+    let mut picks = Solution(vec![BitCube::default(); puzzle.num_pieces]);
+    // Pieces in the order they were placed, for undo
+    let mut placed: Vec<usize> = Vec::new();
+
+    println!("{}", INTERACTIVE_HELP);
+    println!();
+    print_solution(puzzle, pieces, &picks, color)?;
+    let mut lines = std::io::stdin().lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Option<Vec<usize>> = words.iter().skip(1).map(|w| w.parse().ok()).collect();
+        match (words.first().copied(), numbers.as_deref()) {
+            (None, _) => continue,
+            (Some("quit" | "exit"), _) => return Ok(()),
+            (Some("help"), _) => println!("{}", INTERACTIVE_HELP),
+            (Some("show"), _) => print_solution(puzzle, pieces, &picks, color)?,
+            (Some("undo"), _) => match placed.pop() {
+                Some(piece) => {
+                    state &= !picks.0[piece];
+                    used_pieces &= !(1 << piece);
+                    picks.0[piece] = BitCube::default();
+                    print_solution(puzzle, pieces, &picks, color)?;
+                }
+                None => println!("Nothing to undo"),
+            },
+            (Some(command @ ("fits" | "place")), Some(&[piece, x, y, z, ref rest @ ..])) => {
+                if piece >= puzzle.num_pieces {
+                    println!("There is no piece {}", piece);
+                    continue;
+                }
+                if x >= puzzle.dims[0] || y >= puzzle.dims[1] || z >= puzzle.dims[2] {
+                    println!("Cell {} {} {} is outside the box", x, y, z);
+                    continue;
+                }
+                if used_pieces & (1 << piece) != 0 {
+                    println!("Piece {} is already placed, undo to move it", piece);
+                    continue;
+                }
+                let cell = puzzle.bit_index(x, y, z);
+                if state.test_bit(cell) {
+                    println!("Cell {} {} {} is already filled", x, y, z);
+                    continue;
+                }
+                let fitting: Vec<BitCube> = placements
+                    .fitting(cell, state, used_pieces)
+                    .filter(|(p, _)| *p == piece)
+                    .map(|(_, placement)| placement)
+                    .collect();
+                let placement = match (command, rest) {
+                    ("place", []) if fitting.len() == 1 => fitting[0],
+                    ("place", [n]) if *n < fitting.len() => fitting[*n],
+                    ("place", [_]) => {
+                        println!("There are only {} placements that fit", fitting.len());
+                        continue;
+                    }
+                    _ => {
+                        println!("{} placements of piece {} fit", fitting.len(), piece);
+                        for (n, placement) in fitting.iter().enumerate() {
+                            println!();
+                            println!("{}:", n);
+                            let mut preview = picks.clone();
+                            preview.0[piece] = *placement;
+                            print_solution(puzzle, pieces, &preview, color)?;
+                        }
+                        continue;
+                    }
+                };
+                picks.0[piece] = placement;
+                state |= placement;
+                used_pieces |= 1 << piece;
+                placed.push(piece);
+                print_solution(puzzle, pieces, &picks, color)?;
+                if placed.len() == puzzle.num_pieces {
+                    if validate_solution(puzzle, &picks) {
+                        println!("Solved!");
+                    } else {
+                        println!("All pieces are placed, but this isn't a valid solution");
                     }
                 }
             }
+            _ => println!(
+                "Unknown command: {}, type help for the commands",
+                line.trim()
+            ),
         }
     }
-
-    set.into_iter().collect()
 }
 
-struct Stats {
-    num_permutations: usize,
-    num_solutions: usize,
-
-    last_print: std::time::Instant,
-    last_print_permutations: usize,
+/// Print a piece to stdout, in its color if `color` is set
+fn print_piece(
+    puzzle: &Puzzle,
+    pieces: &[Piece],
+    piece: usize,
+    color: bool,
+) -> Result<(), std::io::Error> {
+    if color {
+        let mut solution = Solution(vec![BitCube::default(); pieces.len()]);
+        solution.0[piece] = pieces[piece].bits;
+        print_solution(puzzle, pieces, &solution, color)
+    } else {
+        print(puzzle, pieces[piece].bits);
+        Ok(())
+    }
 }
 
-impl Stats {
-    fn new() -> Self {
-        Self {
-            num_permutations: 0,
-            num_solutions: 0,
-            last_print: std::time::Instant::now(),
-            last_print_permutations: 0,
+fn main() -> Result<(), Error> {
+    let mut args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("bedlam-cube-rs {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    // Fall back to plain text when the output is redirected
+    let color = args.color && std::io::stdout().is_terminal();
+
+    // The number of pieces is only known after reading them
+    let mut puzzle = match args.dims {
+        Some(dims) => Puzzle::new_box(dims, 0),
+        None => Puzzle::new(args.size, 0),
+    };
+    let pieces_format = args
+        .pieces_format
+        .unwrap_or(if args.pieces.ends_with(".json") {
+            PieceFormat::Json
+        } else {
+            PieceFormat::Text
+        });
+    let mut pieces = match pieces_format {
+        PieceFormat::Text if args.pieces == "-" => select_puzzle(
+            read_puzzles_from(&puzzle, std::io::stdin().lock(), "<stdin>", args.grid_chars)?,
+            &args,
+        ),
+        PieceFormat::Json if args.pieces == "-" => {
+            read_pieces_json_from(&puzzle, std::io::stdin().lock(), "<stdin>")
         }
+        PieceFormat::Text => {
+            select_puzzle(read_puzzles(&puzzle, &args.pieces, args.grid_chars)?, &args)
+        }
+        PieceFormat::Json if args.puzzle.is_some() => Err(Error::Parse(
+            "--puzzle needs a piece file in the text format".to_string(),
+        )),
+        PieceFormat::Json if args.grid_chars != GridChars::default() => Err(Error::Parse(
+            "--grid-chars needs a piece file in the text format".to_string(),
+        )),
+        PieceFormat::Json => read_pieces_json(&puzzle, &args.pieces),
+    }?;
+    puzzle.num_pieces = pieces.len();
+    validate_pieces(&puzzle, &pieces)?;
+    if !args.exclude.is_empty() {
+        if let Some(id) = args.exclude.iter().find(|id| **id >= pieces.len()) {
+            return Err(Error::Parse(format!(
+                "Can't exclude piece {}, there are only {} pieces",
+                id,
+                pieces.len()
+            )));
+        }
+        pieces.retain(|piece| !args.exclude.contains(&piece.id));
+        // The remaining pieces are numbered from 0 again, like when read
+        for (id, piece) in pieces.iter_mut().enumerate() {
+            piece.id = id;
+        }
+        puzzle.num_pieces = pieces.len();
+        log!(
+            args.verbosity,
+            Verbosity::Normal,
+            "Excluded pieces {:?}, the other pieces are numbered from 0 again",
+            args.exclude
+        );
     }
-    fn print(&mut self) {
-        let now = std::time::Instant::now();
-        let elapsed = (now - self.last_print).as_secs_f64();
-        if elapsed < 1.0 {
-            return;
+    let piece_bits: Vec<_> = pieces.iter().map(|piece| piece.bits).collect();
+    let piece_fixed: Vec<_> = pieces.iter().map(|piece| piece.fixed).collect();
+
+    let verbosity = args.verbosity;
+    // Piece map for reflections, if mirror images count as the same solution
+    let mirror = if args.with_mirror {
+        let mirror = mirror_pieces(&puzzle, &piece_bits);
+        if mirror.is_none() {
+            log!(
+                verbosity,
+                Verbosity::Normal,
+                "The mirror image of some piece is missing, no solution has a mirrored solution"
+            );
         }
+        mirror
+    } else {
+        None
+    };
 
-        let permutations = self.num_permutations - self.last_print_permutations;
+    if let Some(dir) = &args.merge_shards {
+        let solutions = merge_shards(&puzzle, dir)?;
+        let unique_solutions = filter_unique_solutions(&puzzle, &solutions, mirror.as_deref());
+        println!("Merged {} unique solutions", unique_solutions.len());
+        write_solution_files(&puzzle, &pieces, &unique_solutions, &args)?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.verify {
+        // A solutions file, or a single solution as written by write_solution
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", path, err)))?;
+        let solutions = if contents.lines().any(|line| line.starts_with("Solution #")) {
+            read_solutions(&puzzle, std::path::Path::new(path))?
+        } else {
+            vec![parse_solution(&puzzle, &contents, path)?]
+        };
+        let mut num_invalid = 0;
+        for (i, solution) in solutions.iter().enumerate() {
+            let problems = verify_solution(&puzzle, &pieces, solution);
+            if problems.is_empty() {
+                log!(verbosity, Verbosity::Verbose, "Solution {} is valid", i);
+                continue;
+            }
+            num_invalid += 1;
+            println!("Solution {} is not valid:", i);
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
         println!(
-            "Permutations: {}, Solutions: {}, Permutations/s: {}",
-            self.num_permutations,
-            self.num_solutions,
-            permutations as f64 / elapsed,
+            "{} of {} solutions are valid",
+            solutions.len() - num_invalid,
+            solutions.len()
         );
-        self.last_print = now;
-        self.last_print_permutations = self.num_permutations;
+        if num_invalid > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
-    fn success(&mut self) {
-        self.num_solutions += 1;
-        self.num_permutations += 1;
+
+    if let Some(index) = args.animate {
+        let solutions = read_solutions(&puzzle, std::path::Path::new(&args.solutions))?;
+        let Some(solution) = solutions.get(index) else {
+            println!(
+                "There is no solution {} in {}, it has {} solutions",
+                index,
+                args.solutions,
+                solutions.len()
+            );
+            std::process::exit(1);
+        };
+        return animate(&puzzle, &pieces, solution, index, &args, color);
     }
-    fn fail(&mut self) {
-        self.num_permutations += 1;
+
+    if let Some((path_a, path_b)) = &args.diff {
+        let a = read_solutions(&puzzle, std::path::Path::new(path_a))?;
+        let b = read_solutions(&puzzle, std::path::Path::new(path_b))?;
+        let (only_a, only_b) = diff_solutions(&puzzle, &a, &b, mirror.is_some());
+        for (path, only) in [(path_a, &only_a), (path_b, &only_b)] {
+            println!("{} solutions only in {}", only.len(), path);
+            for solution in only {
+                println!();
+                print_solution(&puzzle, &pieces, solution, color)?;
+            }
+        }
+        if !only_a.is_empty() || !only_b.is_empty() {
+            std::process::exit(1);
+        }
+        println!("The solutions are the same");
+        return Ok(());
     }
-}
 
-/// Search algorithm
-/// state: bit mask of the current state of the cube
-/// used_pieces: bit mask of the pieces that have been used
-/// bit_map: for each bit in the cube, map it to a list of pieces and piece placement that fit that bit
-///         bit_map[bit_index][piece] = [placement0, placement1, ...]
-/// picks: stack for keeping track of picked pieces (piece_id, placement)
-fn search(
-    state: u64,
-    used_pieces: u64,
-    bit_map: &Vec<Vec<Vec<u64>>>,
-    picks: &mut [u64; NUM_PIECES],
-    stats: &mut Stats,
-    solutions: &mut Vec<Solution>,
-) {
-    stats.print();
-    if used_pieces.count_ones() == NUM_PIECES as u32 {
-        // Slows down things quite a lot, but prints each solution
-        // print_solution(picks);
-        // println!();
-        solutions.push(Solution(picks.clone()));
-        stats.success();
-        return;
-    }
-
-    // Find first empty bit in the cube, starting from the least significant bit (first x=0)
-    let bit_index = state.trailing_ones() as usize;
-
-    // For each piece that fits this bit, recurse
-    for piece in 0..NUM_PIECES {
-        if used_pieces & (1 << piece) != 0 {
-            continue;
-        }
-        for permutation in bit_map[bit_index][piece].iter() {
-            if (*permutation & state) == 0 {
-                picks[piece] = *permutation;
-                search(
-                    state | *permutation,
-                    used_pieces | 1 << piece,
-                    bit_map,
-                    picks,
-                    stats,
-                    solutions,
-                );
+    if args.dump_pieces {
+        let piece_placements = generate_all_placements(&puzzle, &piece_bits, &piece_fixed);
+        for (piece, placements) in pieces.iter().zip(&piece_placements) {
+            match &piece.name {
+                Some(name) => print!("Piece {} ({})", piece.id, name),
+                None => print!("Piece {}", piece.id),
             }
+            println!(
+                ": {} cells, {} orientations, {} placements",
+                piece.bits.count_ones(),
+                if piece.fixed {
+                    1
+                } else {
+                    orientations(&puzzle, piece.bits).len()
+                },
+                placements.len()
+            );
+            print(&puzzle, canonical_piece(&puzzle, piece.bits));
+            println!();
+            print_projections(&puzzle, canonical_piece(&puzzle, piece.bits));
+            println!();
         }
+        return Ok(());
+    }
+
+    if args.interactive {
+        let piece_placements = generate_all_placements(&puzzle, &piece_bits, &piece_fixed);
+        let placements = Placements::new(puzzle, piece_placements, !args.no_bit_map);
+        return interactive(&puzzle, &pieces, &placements, color);
     }
-    stats.fail();
-}
 
-/// Returns a filtered version of the solutions with only unique solutions
-fn filter_unique_solutions(solutions: &Vec<Solution>) -> Vec<Solution> {
-    let mut unique_solutions = Vec::new();
+    if verbosity >= Verbosity::Verbose {
+        for piece in &pieces {
+            match &piece.name {
+                Some(name) => println!("Piece {} ({})", piece.id, name),
+                None => println!("Piece {}", piece.id),
+            }
+            print_piece(&puzzle, &pieces, piece.id, color)?;
+            println!();
+        }
+    }
 
-    // All seen solutions
-    let mut hashes = std::collections::HashSet::new();
-    for solution in solutions {
-        if !hashes.insert(hash_solution(&solution)) {
-            // Already seen this solution
-            continue;
+    log!(verbosity, Verbosity::Normal, "Read {} pieces", pieces.len());
+    if !args.allow_duplicates {
+        for group in congruent_pieces(&puzzle, &piece_bits) {
+            log!(
+                verbosity,
+                Verbosity::Normal,
+                "Warning: pieces {:?} have the same shape",
+                group
+            );
         }
+    }
 
-        // Add this solution and any (rotated) permutations of it to the set of seen solutions
-        unique_solutions.push(solution.clone());
+    let mut piece_placements = match &args.cache {
+        Some(dir) => cached_placements(&puzzle, &piece_bits, &piece_fixed, dir, verbosity)?,
+        None => generate_all_placements(&puzzle, &piece_bits, &piece_fixed),
+    };
+    for piece in pieces.iter().filter(|piece| piece.fixed) {
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Piece {} is fixed in its orientation",
+            piece.id
+        );
+    }
 
-        let mut solution = solution.clone();
-        for _ in 0..4 {
-            for _ in 0..4 {
-                for _ in 0..4 {
-                    for p in 0..NUM_PIECES {
-                        solution.0[p] = rotate_piece_90(solution.0[p], Axis::X);
-                    }
-                    hashes.insert(hash_solution(&solution));
-                }
-                for p in 0..NUM_PIECES {
-                    solution.0[p] = rotate_piece_90(solution.0[p], Axis::Y);
-                }
-                hashes.insert(hash_solution(&solution));
-            }
-            for p in 0..NUM_PIECES {
-                solution.0[p] = rotate_piece_90(solution.0[p], Axis::Z);
-            }
-            hashes.insert(hash_solution(&solution));
+    log!(
+        verbosity,
+        Verbosity::Normal,
+        "Generated {} permutations",
+        piece_placements.iter().map(Vec::len).sum::<usize>()
+    );
+    for (piece, placements) in piece_placements.iter().enumerate() {
+        log!(
+            verbosity,
+            Verbosity::Verbose,
+            "Piece {}: {} permutations",
+            piece,
+            placements.len()
+        );
+        for placement in placements {
+            log!(verbosity, Verbosity::Verbose, "  {:#x}", placement);
         }
     }
-    unique_solutions
-}
 
-fn main() {
-    let pieces = read_pieces("pieces.txt").expect("Failed to read pieces");
-    for (piece, piece_bits) in pieces.iter().enumerate() {
-        println!("Piece {}", piece);
-        print(*piece_bits);
-        println!();
+    // Pieces sharing a placement are still told apart by the search, but it usually
+    // means that the pieces have the same shape
+    for (placement, pieces) in shared_placements(&piece_placements) {
+        log!(
+            verbosity,
+            Verbosity::Verbose,
+            "Placement {:#018x} shared by pieces {:?}",
+            placement,
+            pieces
+        );
     }
 
-    println!("Read {} pieces", pieces.len());
-    println!();
-    if pieces.len() != NUM_PIECES {
-        panic!("Expected {} pieces, got {}", NUM_PIECES, pieces.len());
+    // The first pieces of the seed solution are fixed just like with --fix
+    if let Some((path, num_seeded)) = args.seed_solution.clone() {
+        let seed = read_solutions(&puzzle, std::path::Path::new(&path))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Parse(format!("{}: no solutions to seed from", path)))?;
+        if num_seeded > puzzle.num_pieces {
+            return Err(Error::InvalidFix(format!(
+                "can't seed {} pieces, there are only {}",
+                num_seeded, puzzle.num_pieces
+            )));
+        }
+        args.fix
+            .extend((0..num_seeded).map(|piece| (piece, seed.0[piece])));
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Seeded with the first {} pieces of the first solution in {}",
+            num_seeded,
+            path
+        );
     }
 
-    let piece_placements = pieces
-        .into_iter()
-        .map(generate_placements)
-        .collect::<Vec<_>>();
+    if !args.fix.is_empty() {
+        fix_placements(&puzzle, &mut piece_placements, &args.fix)?;
+        for (piece, placement) in &args.fix {
+            log!(
+                verbosity,
+                Verbosity::Normal,
+                "Piece {} fixed at {:x}",
+                piece,
+                placement
+            );
+        }
+    }
 
-    for (piece, placements) in piece_placements.iter().enumerate() {
-        println!("Piece {}: {} permutations", piece, placements.len());
+    if !args.require.is_empty() {
+        // The same checks as for --fix, without restricting the placements
+        fix_placements(&puzzle, &mut piece_placements.clone(), &args.require)?;
+        for (piece, placement) in &args.require {
+            log!(
+                verbosity,
+                Verbosity::Normal,
+                "Piece {} required at {:x}",
+                piece,
+                placement
+            );
+        }
     }
-    println!();
 
-    // For every bit in the block, map it to a each piece and permutation
-    let mut bit_map: Vec<Vec<Vec<u64>>> = vec![vec![Vec::new(); NUM_PIECES]; CUBE_NUM_BITS];
-    for bi in 0..CUBE_NUM_BITS {
-        for pi in 0..NUM_PIECES {
-            let map_placement = &mut bit_map[bi][pi];
-            for placement in piece_placements[pi].iter() {
-                if placement & (1 << bi) != 0 {
-                    map_placement.push(*placement);
-                }
-            }
+    // Quick proof that there is no solution for some piece sets, such as ones with a single
+    // T tetromino among pieces that cover as many cells of each color as they can
+    for num_colors in [2, 3] {
+        if !coloring_feasible(&puzzle, &piece_placements, num_colors) {
+            println!(
+                "No solution exists: the pieces can't cover the cells of each color when the cube is colored by (x + y + z) mod {}",
+                num_colors
+            );
+            return Err(Error::NoSolution);
         }
     }
 
+    // Fixed pieces already rule out the rotations of a solution, unless the fixed pieces
+    // are symmetric, restricting another piece could skip solutions. The same goes for
+    // pieces that can't be rotated.
+    let symmetry_breaking =
+        args.fix.is_empty() && args.require.is_empty() && !piece_fixed.contains(&true);
+    if args.bench {
+        bench(&puzzle, &piece_placements, &args, symmetry_breaking);
+        return Ok(());
+    }
+    if !args.no_symmetry_breaking && symmetry_breaking {
+        match break_symmetry(&puzzle, &mut piece_placements) {
+            Some(piece) => log!(
+                verbosity,
+                Verbosity::Normal,
+                "Piece {} restricted to {} permutations to skip rotated solutions",
+                piece,
+                piece_placements[piece].len()
+            ),
+            None => log!(
+                verbosity,
+                Verbosity::Normal,
+                "No piece without rotational symmetry, searching all rotations"
+            ),
+        }
+    }
+    // Placements are sorted, so the same ones are kept in every run
+    if let Some(max) = args.max_placements_per_piece {
+        for placements in &mut piece_placements {
+            placements.truncate(max);
+        }
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Only searching the first {} placements of each piece, the search is not exhaustive",
+            max
+        );
+    }
+    log!(verbosity, Verbosity::Normal, "");
+
+    let placements = Placements::new(puzzle, piece_placements, !args.no_bit_map);
+    // Top level branches, every placement covering the first cell the search branches on
+    let first_cell = args.heuristic.select(BitCube::default(), 0, &placements);
+    // Leaving the cell empty is a branch as well, if the pieces can't fill the box
+    let num_branches: usize = (0..puzzle.num_pieces)
+        .map(|piece| placements.for_cell(first_cell, piece).count())
+        .sum::<usize>()
+        + usize::from(placements.num_holes > 0);
+    if args.estimate {
+        print_estimate(&puzzle, &placements, first_cell, num_branches);
+        return Ok(());
+    }
+
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |d| d.as_nanos() as u64)
+    });
+    if args.random || args.sub_puzzle.is_some() {
+        eprintln!("Seed: {}", seed);
+    }
+
     let start = std::time::Instant::now();
 
     let mut stats = Stats::new();
+    stats.quiet = verbosity == Verbosity::Quiet;
+    if args.count_unique || args.max_unique.is_some() {
+        stats.unique_solutions = Some(std::collections::HashSet::new());
+        stats.mirror = mirror.clone();
+    }
+    if args.profile_branching {
+        stats.branching = Some(vec![(0, 0); puzzle.num_pieces + placements.num_holes]);
+    }
+    if args.profile_pieces {
+        stats.piece_stats = Some(vec![(0, 0); puzzle.num_pieces]);
+    }
+    if args.histogram {
+        stats.solution_times = Some(Vec::new());
+    }
     // Keeping track of picked pieces
-    let mut picks = [0_u64; NUM_PIECES];
+    let mut picks = Solution(vec![BitCube::default(); puzzle.num_pieces]);
     let mut solutions = Vec::new();
-    search(0, 0, &bit_map, &mut picks, &mut stats, &mut solutions);
+    let mut config = SolverConfig::new()
+        .box_dims(puzzle.dims)
+        .threads(args.threads)
+        .heuristic(args.heuristic)
+        .piece_order(args.piece_order)
+        .quick_feasible(args.quick_feasible)
+        .prune_regions(args.prune_regions)
+        .forward_checking(args.forward_checking);
+    if let Some(max_solutions) = args.stop_after {
+        config = config.max_solutions(max_solutions);
+    }
+    if args.sub_puzzle.is_some() {
+        config = config.max_solutions(1);
+    }
+    // The options only the command line has, on top of the ones of the config
+    let options = SearchOptions {
+        max_depth: args.limit_depth.unwrap_or(puzzle.num_pieces),
+        branches: args
+            .shard
+            .map(|(index, count)| num_branches * index / count..num_branches * (index + 1) / count),
+        max_unique_solutions: args.max_unique,
+        random_seed: args.random.then_some(seed),
+        required: args.require.clone(),
+        ..config.search_options(puzzle.num_pieces)
+    };
+    if let Some(path) = &args.resume {
+        let checkpoint = read_checkpoint(&puzzle, std::path::Path::new(path))?;
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Resuming after {} permutations and {} solutions",
+            checkpoint.num_permutations,
+            checkpoint.num_solutions
+        );
+        stats.restore(&checkpoint);
+        solutions = checkpoint.solutions;
+    }
+    if let Some(timeout) = args.timeout {
+        stats.deadline = Some(start + std::time::Duration::from_secs(timeout));
+    }
+    if args.checkpoint.is_some() {
+        stats.checkpoint_interval = Some(std::time::Duration::from_secs(args.checkpoint_interval));
+    }
+    let keep_solutions = !args.count && !args.count_unique && !args.stream;
+    // Only the unique solutions are needed on several threads, so they can be filtered as
+    // they are found instead of keeping all of them, unless the search stops after some
+    let deduplicate = args.threads > 1
+        && keep_solutions
+        && options.max_depth == puzzle.num_pieces
+        && args.sub_puzzle.is_none()
+        && args.stop_after.is_none()
+        && args.max_unique.is_none()
+        && !args.no_unique;
+    let mut unique_writer = if args.stream {
+        Some(UniqueWriter::new(
+            puzzle,
+            pieces.clone(),
+            mirror.clone(),
+            std::io::BufWriter::new(create_file(&args.solutions)?),
+        ))
+    } else {
+        None
+    };
+    // The search can't be stopped from the callback, so only the first write error is kept
+    let mut write_result = Ok(());
+    let stopped = if args.solver == Backend::Dlx {
+        Dlx::new(&placements).search(&puzzle, &mut picks, &options, &mut stats, &mut |solution| {
+            if let Some(writer) = &mut unique_writer {
+                if write_result.is_ok() {
+                    write_result = writer.write(solution);
+                }
+            } else if keep_solutions {
+                solutions.push(solution.clone())
+            }
+        })
+    } else if deduplicate {
+        let stopped;
+        (solutions, stopped) = parallel_unique_search(
+            &placements,
+            &options,
+            num_branches,
+            args.threads,
+            mirror.as_deref(),
+            &mut stats,
+        );
+        stopped
+    } else if args.threads > 1 {
+        let stopped;
+        (solutions, stopped) = parallel_search(
+            &placements,
+            &options,
+            num_branches,
+            args.threads,
+            keep_solutions,
+            &mut stats,
+        );
+        stopped
+    } else {
+        loop {
+            let stopped = search(
+                BitCube::default(),
+                0,
+                &placements,
+                &mut picks,
+                &options,
+                &mut stats,
+                &mut |solution| {
+                    if let Some(writer) = &mut unique_writer {
+                        if write_result.is_ok() {
+                            write_result = writer.write(solution);
+                        }
+                    } else if keep_solutions {
+                        solutions.push(solution.clone())
+                    }
+                },
+            );
+            // The search stops every checkpoint interval, write the checkpoint and resume
+            let (Some(picks), Some(path)) = (stats.checkpoint.take(), &args.checkpoint) else {
+                break stopped;
+            };
+            let checkpoint = Checkpoint {
+                picks,
+                num_permutations: stats.num_permutations,
+                num_solutions: stats.num_solutions,
+                progress: stats.progress,
+                solutions,
+            };
+            // Write to a temporary file first so that a crash never leaves a partial checkpoint
+            let tmp_path = format!("{}.tmp", path);
+            let mut file = create_file(&tmp_path)?;
+            write_checkpoint(&puzzle, &pieces, &checkpoint, &mut file)?;
+            std::fs::rename(&tmp_path, path)?;
+            solutions = checkpoint.solutions;
+        }
+    };
+    write_result?;
+    stats.print_branching();
+    stats.print_piece_stats();
+    stats.print_histogram();
+    // Also printed when the search was cut short, to tell whether --count or --stream is needed
+    log!(
+        verbosity,
+        Verbosity::Normal,
+        "Memory: {} for placements, {} for {} solutions",
+        format_bytes(placements.memory_usage()),
+        format_bytes(solutions.len() * puzzle.num_pieces * std::mem::size_of::<BitCube>()),
+        solutions.len()
+    );
+    if stats.timed_out {
+        eprintln!(
+            "Search timed out after {} solutions, reporting the solutions found so far",
+            stats.num_solutions
+        );
+    } else if stopped {
+        eprintln!("Search stopped after {} solutions", stats.num_solutions);
+    }
+    // Printed with the results, so the counts aren't mistaken for those of a full search
+    if let Some(max) = args.max_placements_per_piece {
+        println!(
+            "Non-exhaustive search: only the first {} placements of each piece were searched",
+            max
+        );
+    }
+
+    // Zero solutions only prove that the pieces can't fill the box if the search was complete,
+    // in which case the exit code is non-zero once the results have been reported
+    let complete = !stats.timed_out
+        && !stopped
+        && args.max_placements_per_piece.is_none()
+        && options.max_depth == puzzle.num_pieces;
+    if stats.num_solutions == 0 && complete {
+        println!("No solution exists: the search was complete and found none");
+    } else if stats.num_solutions == 0 && options.max_depth == puzzle.num_pieces {
+        println!("No solution found, but the search wasn't complete and one may still exist");
+    }
+    let finished = || {
+        if stats.num_solutions == 0 && complete {
+            Err(Error::NoSolution)
+        } else {
+            Ok(())
+        }
+    };
+
+    if let Some((_, num_seeded)) = &args.seed_solution {
+        println!(
+            "{} completions share the first {} pieces of the seed solution",
+            stats.num_solutions, num_seeded
+        );
+    }
+
+    if let Some(num_removed) = args.sub_puzzle {
+        let solution = solutions.first().ok_or(Error::NoSolution)?;
+        let (sub_puzzle, removed) = make_sub_puzzle(solution, num_removed, &mut Rng::new(seed));
+        println!();
+        print_solution(&puzzle, &pieces, &sub_puzzle, color)?;
+        println!();
+        println!("Pieces to place: {:?}", removed);
+        for piece in removed {
+            println!();
+            println!("Piece {}", piece);
+            print_piece(&puzzle, &pieces, piece, color)?;
+        }
+        return Ok(());
+    }
+
+    if options.max_depth < puzzle.num_pieces {
+        println!(
+            "Reached {} partial states with {} pieces placed",
+            solutions.len(),
+            options.max_depth
+        );
+        for solution in solutions.iter().take(3) {
+            println!();
+            print_solution(&puzzle, &pieces, solution, color)?;
+        }
+        return Ok(());
+    }
+
+    let print_took = || {
+        eprintln!(
+            "Took {} seconds",
+            (std::time::Instant::now() - start).as_secs_f64()
+        );
+        if let Some(time) = stats.first_solution_time {
+            eprintln!("Time to first solution: {} seconds", time);
+        }
+    };
+
+    let report_stats = |num_unique: Option<usize>| match &args.stats_json {
+        Some(path) => write_stats_json(
+            path,
+            &stats,
+            num_unique,
+            (std::time::Instant::now() - start).as_secs_f64(),
+            stopped,
+        ),
+        None => Ok(()),
+    };
+
+    if args.count {
+        println!("Found {} solutions", stats.num_solutions);
+        print_took();
+        report_stats(None)?;
+        return finished();
+    }
+
+    // Printed after the number of unique solutions when the search stopped at --max-unique
+    let capped = |num_unique: usize| {
+        if args.max_unique.is_some_and(|max| num_unique >= max) {
+            " (stopped at --max-unique)"
+        } else {
+            ""
+        }
+    };
+
+    if let Some(writer) = &unique_writer {
+        println!(
+            "Found {} unique solutions{}",
+            writer.num_written(),
+            capped(writer.num_written())
+        );
+        print_took();
+        report_stats(Some(writer.num_written()))?;
+        return finished();
+    }
+
+    if let (true, Some(unique_solutions)) = (args.count_unique, &stats.unique_solutions) {
+        println!(
+            "Found {} unique solutions{}",
+            unique_solutions.len(),
+            capped(unique_solutions.len())
+        );
+        print_took();
+        report_stats(Some(unique_solutions.len()))?;
+        return finished();
+    }
+
+    let branches = options.branches.clone().unwrap_or(0..num_branches);
+    // Every solution as found, rotations and all
+    if args.no_unique {
+        if let Some(sort_by) = args.sort_by {
+            sort_solutions(&puzzle, &mut solutions, sort_by, mirror.is_some());
+        }
+        println!("Found {} solutions (not deduplicated)", solutions.len());
+        print_took();
+        report_stats(None)?;
+        write_results(&puzzle, &pieces, &solutions, &args, branches, num_branches)?;
+        return finished();
+    }
 
     // Filter out unique solutions
-    let unique_solutions = filter_unique_solutions(&solutions);
+    let mut unique_solutions = filter_unique_solutions(&puzzle, &solutions, mirror.as_deref());
+    // Several threads can find more unique solutions before they all stop
+    if let Some(max) = args.max_unique {
+        unique_solutions.truncate(max);
+    }
+    if let Some(sort_by) = args.sort_by {
+        sort_solutions(&puzzle, &mut unique_solutions, sort_by, mirror.is_some());
+    }
 
-    println!("Found {} unique solutions", unique_solutions.len());
+    // The solutions are unique already if they were filtered during the search
+    let num_solutions = if deduplicate {
+        stats.num_solutions
+    } else {
+        solutions.len()
+    };
+    println!("Found {} solutions", num_solutions);
     println!(
-        "Took {} seconds",
-        (std::time::Instant::now() - start).as_secs_f64()
+        "Found {} unique solutions{}",
+        unique_solutions.len(),
+        capped(unique_solutions.len())
     );
-
-    //Write solutions to file
-    let mut file = std::fs::File::create("solutions.txt").expect("Failed to create file");
-    for (i, solution) in unique_solutions.iter().enumerate() {
-        let write_fn = |file: &mut std::fs::File| -> Result<(), std::io::Error> {
-            writeln!(file, "Solution #{}", i)?;
-            write_solution(solution, file)?;
-            writeln!(file)?;
-            Ok(())
-        };
-        write_fn(&mut file).expect("Failed to write to file");
+    // Without symmetry breaking every unique solution is found once per symmetry, unless
+    // the solution is symmetric itself, so a different factor points to such solutions or a bug
+    if !unique_solutions.is_empty() {
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Symmetry factor: {:.2} solutions per unique solution, of {} symmetries",
+            num_solutions as f64 / unique_solutions.len() as f64,
+            solution_symmetries(&puzzle, mirror.as_deref()).len()
+        );
+        let num_symmetric = unique_solutions
+            .iter()
+            .filter(|solution| stabilizer_size(&puzzle, solution, mirror.is_some()) > 1)
+            .count();
+        log!(
+            verbosity,
+            Verbosity::Normal,
+            "Self-symmetric solutions: {}, mapped onto themselves by a symmetry other than the identity",
+            num_symmetric
+        );
     }
+    print_took();
+    report_stats(Some(unique_solutions.len()))?;
+
+    write_results(
+        &puzzle,
+        &pieces,
+        &unique_solutions,
+        &args,
+        branches,
+        num_branches,
+    )?;
+    finished()
 }